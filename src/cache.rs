@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::UNIX_EPOCH;
+
+use failure::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::cfg::Resolution;
+use crate::cond::Facts;
+use crate::cfgreader::{self, ConfigPaths};
+
+pub const BINLINK_CACHE_DIR_EVAR: &str = "BINLINK_CACHE_DIR";
+pub const BINLINK_NO_CACHE_EVAR: &str = "BINLINK_NO_CACHE";
+
+/// On-disk snapshot of resolved binaries for one set of config files on one machine.
+///
+/// The file is valid while the facts match and the config files keep their mtimes,
+/// each entry additionally while everything it depended on stays the same.
+#[derive(Serialize, Deserialize, Debug)]
+struct CacheFile {
+    facts: Facts,
+    sources: Vec<Stamp>,
+    bins: BTreeMap<String, Resolution>,
+}
+
+/// Something a resolution depended on, as it was at resolution time.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum Stamp {
+    File {
+        path: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mtime: Option<i64>,
+    },
+    Var {
+        var: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        value: Option<String>,
+    },
+}
+
+impl Stamp {
+    pub fn of(path: &Path) -> Stamp {
+        let mtime = fs::metadata(path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos() as i64);
+        Stamp::File { path: path.to_owned(), mtime }
+    }
+
+    pub fn var(name: &str) -> Stamp {
+        let value = env::var_os(name).map(|v| v.to_string_lossy().into_owned());
+        Stamp::Var { var: String::from(name), value }
+    }
+
+    fn is_fresh(&self) -> bool {
+        match self {
+            Stamp::File { path, .. } => *self == Stamp::of(path),
+            Stamp::Var { var, .. } => *self == Stamp::var(var),
+        }
+    }
+
+    fn key(&self) -> &OsStr {
+        match self {
+            Stamp::File { path, .. } => path.as_os_str(),
+            Stamp::Var { var, .. } => OsStr::new(var),
+        }
+    }
+}
+
+/// Resolves `name` for the current directory, reusing the on-disk cache when it is still valid.
+pub fn resolve(name: &str) -> Result<Resolution, Error> {
+    let paths = cfgreader::get_config_paths()?;
+
+    match cache_dir() {
+        Some(dir) if env::var_os(BINLINK_NO_CACHE_EVAR).is_none() => resolve_cached(&paths, name, &dir),
+        _ => cfgreader::parse_config(&paths)?.resolve(name),
+    }
+}
+
+fn resolve_cached(paths: &ConfigPaths, name: &str, dir: &Path) -> Result<Resolution, Error> {
+    // stamps are taken before parsing, so a concurrent edit can only make the entry stale, never wrong
+    let sources: Vec<Stamp> = paths.all().iter().map(|p| Stamp::of(p)).collect();
+    let facts = Facts::detect();
+    let file = dir.join(cache_file_name(&facts, &sources));
+
+    let mut cached = load(&file)
+        .filter(|c| c.facts == facts && c.sources == sources)
+        .unwrap_or_else(|| CacheFile { facts, sources, bins: BTreeMap::new() });
+
+    if let Some(hit) = cached.bins.get(name).filter(|r| r.watched.iter().all(|s| s.is_fresh())) {
+        log::debug!("using cached resolution of {} from {:?}", name, file);
+        return Ok(hit.clone());
+    }
+
+    let resolution = cfgreader::parse_config(paths)?.resolve(name)?;
+
+    cached.bins.insert(String::from(name), resolution.clone());
+    // fails for paths which are not valid UTF-8, such resolutions are simply never cached
+    if let Err(e) = store(&file, &cached) {
+        log::debug!("cannot write cache {:?}: {}", file, e);
+    }
+
+    Ok(resolution)
+}
+
+pub fn clear() {
+    match cache_dir() {
+        Some(dir) if dir.exists() => {
+            match fs::remove_dir_all(&dir) {
+                Ok(_) => println!("removed: {}", dir.display()),
+                Err(e) => println!("cannot remove {}: {:#?}", dir.display(), e),
+            }
+        }
+        _ => println!("cache is empty"),
+    }
+}
+
+fn cache_dir() -> Option<PathBuf> {
+    match env::var_os(BINLINK_CACHE_DIR_EVAR) {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => dirs::cache_dir().map(|d| d.join("binlink")),
+    }
+}
+
+fn cache_file_name(facts: &Facts, sources: &[Stamp]) -> String {
+    let mut hasher = DefaultHasher::new();
+    facts.hash(&mut hasher);
+    sources.iter().for_each(|s| s.key().hash(&mut hasher));
+    format!("{:016x}.toml", hasher.finish())
+}
+
+fn load(file: &Path) -> Option<CacheFile> {
+    let content = fs::read_to_string(file).ok()?;
+    toml::from_str(&content).ok()
+}
+
+fn store(file: &Path, cached: &CacheFile) -> std::io::Result<()> {
+    // going through `toml::Value` puts plain values ahead of tables regardless of field order
+    let content = toml::Value::try_from(cached)
+        .and_then(|v| toml::to_string(&v))
+        .map_err(std::io::Error::other)?;
+
+    let dir = file.parent().expect("cache file must have a parent");
+    fs::create_dir_all(dir)?;
+
+    // write-then-rename keeps readers from ever seeing a partial file when several shims race,
+    // the loser of a race only drops the other's fresh entry, which gets re-resolved next time
+    let tmp = dir.join(format!(".{}.{}.tmp", process::id(), cache_file_name(&cached.facts, &cached.sources)));
+    fs::write(&tmp, content)?;
+    fs::rename(&tmp, file).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    use crate::cfg::LayerKind;
+    use crate::cfgreader::ConfigSource;
+    use crate::testutil::{executables, temp_dir};
+
+    const TOOL_VAR: &str = "BINLINK_TEST_CACHE_TOOL_HOME";
+
+    fn config(jdks: &Path, prefix: &str) -> String {
+        format!(r#"
+version = 2
+bins = [ {{ name = "java", kit = "jdk" }}, {{ name = "jshell", kit = "tool" }} ]
+kits = [ {{ name = "jdk", id = "jdk" }}, {{ name = "tool", id = "tool" }} ]
+paths = [
+  {{ id = "jdk", target = {{ type = "HighestPrefix", base = "{}", prefix = "{}", path = "bin" }} }},
+  {{ id = "tool", target = {{ type = "Dir", path = "${{{}}}/bin" }} }},
+]
+"#, jdks.display(), prefix, TOOL_VAR)
+    }
+
+    fn write(path: &Path, content: &str, mtime: SystemTime) {
+        fs::write(path, content).unwrap();
+        fs::File::options().write(true).open(path).unwrap().set_modified(mtime).unwrap();
+    }
+
+    #[test]
+    fn reuses_entries_until_something_they_depend_on_changes() {
+        let dir = temp_dir("cache");
+        let jdks = dir.join("jdks");
+        executables(&jdks, &["11/bin/java", "17/bin/java"]);
+        executables(&dir, &["tool1/bin/jshell", "tool2/bin/jshell"]);
+        env::set_var(TOOL_VAR, dir.join("tool1"));
+
+        let path = dir.join("binlink.toml");
+        let mtime = SystemTime::now() - Duration::from_secs(60);
+        write(&path, &config(&jdks, "1"), mtime);
+        let paths = ConfigPaths { sources: vec![ConfigSource { kind: LayerKind::User, path: path.clone() }] };
+        let cache = dir.join("cache");
+        let resolve = |name: &str| resolve_cached(&paths, name, &cache).unwrap().target.unwrap().path;
+
+        assert_eq!(resolve("java"), jdks.join("17/bin/java"));
+        assert_eq!(resolve("jshell"), dir.join("tool1/bin/jshell"));
+
+        // an unchanged mtime means the config isn't read again
+        write(&path, "this is not toml", mtime);
+        assert_eq!(resolve("java"), jdks.join("17/bin/java"));
+        write(&path, &config(&jdks, "1"), mtime);
+
+        executables(&jdks, &["19/bin/java"]);
+        assert_eq!(resolve("java"), jdks.join("19/bin/java"));
+
+        write(&path, &config(&jdks, "11"), mtime + Duration::from_secs(1));
+        assert_eq!(resolve("java"), jdks.join("11/bin/java"));
+
+        env::set_var(TOOL_VAR, dir.join("tool2"));
+        assert_eq!(resolve("jshell"), dir.join("tool2/bin/jshell"));
+    }
+}
@@ -1,137 +1,486 @@
-use std::collections::{HashMap, HashSet};
-
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use failure::{format_err, Error};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::cache::Stamp;
+use crate::cond::{Condition, Facts};
+use crate::detect::version_cmp;
+use crate::interp::{expand, Vars};
+use crate::shorthand::RawConfig;
+
+/// Current config format version, see `binlink migrate`.
+pub const CONFIG_VERSION: i64 = 2;
+
+/// Outcome of resolving a single binary name.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Resolution {
+    /// `None` when the binary should be looked up on `$PATH`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<ResolvedBinary>,
+    /// shown on every run, e.g. when a kit lacks the binary and `$PATH` is used instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+    /// directories scanned while resolving, their mtimes invalidate cached results
+    pub watched: Vec<Stamp>,
+}
+
+impl Resolution {
+    pub fn unresolved() -> Resolution {
+        Resolution { target: None, warning: None, watched: Vec::new() }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResolvedBinary {
+    pub path: PathBuf,
+    pub argv0: Argv0,
+}
+
+/// A binary missing under an explicit `on_missing = { type = "Error" }`, fatal even outside strict mode.
 #[derive(Debug)]
-pub struct ResolvedConfig {
-    pub bins: HashMap<String, String>,
-    pub names: HashSet<String>,
+pub struct MissingBinary(pub String);
+
+impl fmt::Display for MissingBinary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
 }
 
+impl std::error::Error for MissingBinary {}
+
+/// Config files stacked from lowest to highest priority.
 #[derive(Debug)]
 pub struct Config {
-    pub local: Option<LocalConfig>,
-    pub global: Option<GlobalConfig>,
+    pub layers: Vec<Layer>,
+    /// entries whose `when` doesn't match these are ignored
+    pub facts: Facts,
 }
 
+#[derive(Debug)]
+pub struct Layer {
+    pub kind: LayerKind,
+    pub path: PathBuf,
+    /// files pulled in through `include`, in merge order
+    pub includes: Vec<PathBuf>,
+    /// included files and globbed directories, taken before reading them
+    pub watched: Vec<Stamp>,
+    pub config: Box<dyn AbstractConfig>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LayerKind {
+    /// `/etc/binlink/binlink.toml`
+    System,
+    /// `$XDG_CONFIG_HOME/binlink/binlink.toml`
+    User,
+    /// `.binlink.toml` in cwd or any of its parents
+    Project,
+    /// git-ignored `.binlink.local.toml` next to project configs
+    Overlay,
+    /// `.sdkmanrc`, `.nvmrc` etc, below the project config of the same directory
+    Pins,
+}
+
+impl LayerKind {
+    pub fn is_global(&self) -> bool {
+        match self {
+            LayerKind::System | LayerKind::User => true,
+            LayerKind::Project | LayerKind::Overlay | LayerKind::Pins => false,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LayerKind::System => "system",
+            LayerKind::User => "user",
+            LayerKind::Project => "project",
+            LayerKind::Overlay => "overlay",
+            LayerKind::Pins => "pins",
+        }
+    }
+}
 
 impl Config {
-    pub fn resolve(self) -> ResolvedConfig {
-        let globals: (HashMap<String, String>, HashSet<String>) = match self.global {
+    /// All binary names known to any layer.
+    pub fn names(&self) -> HashSet<String> {
+        let sets = self.binsets();
+        self.layers.iter()
+            .flat_map(|l| l.config.bins().iter())
+            .flat_map(|b| match &b.selector {
+                BinSelector::Name { name } => vec![name.as_str()],
+                BinSelector::Set { set } => sets.members(set).unwrap_or_default(),
+            })
+            .map(String::from)
+            .collect()
+    }
+
+    /// Bin sets of all layers, looked up through the stack like kits.
+    pub fn binsets(&self) -> BinSets<'_> {
+        BinSets { defined: self.layers.iter().rev().map(|l| l.config.binsets()).collect() }
+    }
+
+    /// The layer whose mapping of `name` takes effect.
+    pub fn origin_of(&self, name: &str) -> Option<&Layer> {
+        let sets = self.binsets();
+        self.layers.iter().rev().find(|l| l.config.find_bin(name, &self.facts, &sets).is_some())
+    }
+
+    /// Resolves one binary, touching only the kit it points to.
+    ///
+    /// Bins, kits and paths are each looked up through the whole layer stack,
+    /// so a project binary may point at a kit whose machine-specific path lives in the user config.
+    /// The topmost layer mentioning a name wins, including `Default` which pins a binary to `$PATH`.
+    pub fn resolve(&self, name: &str) -> Result<Resolution, Error> {
+        // included files and variables in conditions can change the outcome even when nothing was found
+        let mut watched: Vec<Stamp> = self.layers.iter().flat_map(|l| l.watched.iter().cloned()).collect();
+        watched.extend(self.condition_vars().iter().map(|v| Stamp::var(v)));
+
+        let sets = self.binsets();
+        let bin = match self.layers().into_iter().find_map(|l| l.find_bin(name, &self.facts, &sets)) {
+            Some(bin) => bin,
+            None => return Ok(Resolution { watched, ..Resolution::unresolved() }),
+        };
+
+        let kit_name = match &bin.target {
+            LinkTarget::Sdk { name } => name,
+            LinkTarget::Default => return Ok(Resolution { watched, ..Resolution::unresolved() }),
+        };
+
+        let mut visited = Vec::new();
+        let resolution = self.resolve_in_kit(name, bin, kit_name, &mut visited, &mut watched)?;
+        Ok(Resolution { watched, ..resolution })
+    }
+
+    /// Directory the path of kit `name` currently resolves to, ignoring `on_missing`.
+    pub fn kit_dir(&self, name: &str) -> Result<PathBuf, Error> {
+        let kit = self.layers().into_iter().find_map(|l| l.find_kit(name, &self.facts))
+            .ok_or_else(|| format_err!("undefined kit `{}`", name))?;
+        let (kind, path) = self.layers.iter().rev()
+            .find_map(|l| l.config.find_path(&kit.id, &self.facts).map(|p| (l.kind, p)))
+            .ok_or_else(|| format_err!("kit `{}` points to undefined path `{}`", kit.name, kit.id))?;
+        self.dir_of(kind, path, &mut Vec::new())
+    }
+
+    /// Directory of the innermost project config.
+    pub fn project_root(&self) -> Option<&Path> {
+        self.layers.iter().rev()
+            .find(|l| l.kind == LayerKind::Project)
+            .and_then(|l| l.path.parent())
+    }
+
+    /// Environment variables referenced by any `when`.
+    fn condition_vars(&self) -> BTreeSet<&String> {
+        self.layers().into_iter().flat_map(|l| {
+            let bins = l.bins().iter().map(|b| &b.when);
+            let kits = l.kits().iter().map(|k| &k.when);
+            let paths = l.paths().iter().map(|p| &p.when);
+            bins.chain(kits).chain(paths).flatten().flat_map(|w| w.env.keys()).collect::<Vec<_>>()
+        }).collect()
+    }
+
+    /// Highest priority first.
+    fn layers(&self) -> Vec<&dyn AbstractConfig> {
+        self.layers.iter().rev().map(|l| l.config.as_ref()).collect()
+    }
+
+    fn resolve_in_kit(&self, name: &str, bin: &LinkedBinary, kit_name: &str, visited: &mut Vec<String>, watched: &mut Vec<Stamp>) -> Result<Resolution, Error> {
+        visited.push(String::from(kit_name));
+        // `name` rather than the entry's, entries mapping a set have none
+        let exec = bin.exec.as_deref().unwrap_or(name);
+
+        let kit = match self.layers().into_iter().find_map(|l| l.find_kit(kit_name, &self.facts)) {
+            Some(kit) => kit,
             None => {
-                (HashMap::new(), HashSet::new())
-            }
-            Some(e) => {
-                (e.resolve(), e.bins.into_iter().map(|b| b.name).collect())
+                let reason = format!("`{}` points to undefined kit `{}`", name, kit_name);
+                return self.on_missing_in(None, name, bin, reason, visited, watched);
             }
         };
 
-        let locals: (HashMap<String, String>, HashSet<String>) = match self.local {
+        let found = self.layers.iter().rev()
+            .find_map(|l| l.config.find_path(&kit.id, &self.facts).map(|p| (l.kind, p)));
+        let (kind, path) = match found {
+            Some(found) => found,
             None => {
-                (HashMap::new(), HashSet::new())
+                let reason = format!("kit `{}` points to undefined path `{}`", kit.name, kit.id);
+                return self.on_missing_in(Some(kit), name, bin, reason, visited, watched);
             }
-            Some(e) => {
-                (e.resolve(), e.bins.into_iter().map(|b| b.name).collect())
+        };
+
+        // shown on every run, a version 1 file doesn't say it wants expansion
+        let literal = path.literal_note();
+        let with_note = |message: String| match &literal {
+            Some(note) => format!("{}; {}", message, note),
+            None => message,
+        };
+
+        let dir = self.dir_of(kind, path, watched).map_err(|e| format_err!("{}", with_note(e.to_string())))?;
+        // a binary appearing or vanishing changes the directory mtime
+        watched.push(Stamp::of(&dir));
+
+        match which::which_in(exec, Some(&dir), &dir) {
+            Ok(found) => {
+                let resolved = ResolvedBinary {
+                    path: found,
+                    argv0: bin.argv0.clone().unwrap_or(Argv0::Resolved),
+                };
+                Ok(Resolution { target: Some(resolved), warning: literal, watched: Vec::new() })
+            }
+            Err(_) => {
+                let reason = with_note(format!("kit `{}` has no `{}` in {}", kit.name, exec, dir.display()));
+                self.on_missing_in(Some(kit), name, bin, reason, visited, watched)
             }
+        }
+    }
+
+    fn dir_of(&self, kind: LayerKind, path: &KitPath, watched: &mut Vec<Stamp>) -> Result<PathBuf, Error> {
+        let config_dir = path.origin.as_ref().and_then(|o| o.parent());
+        let vars = Vars {
+            config_dir,
+            project_root: self.project_root(),
+            facts: &self.facts,
+            // checked-in project configs must not depend on the cwd they are used from
+            relative_to: if kind.is_global() { None } else { config_dir },
+            literal: path.literal,
         };
-        let out = globals.0.into_iter().chain(locals.0).collect();
-        let out_n = globals.1.into_iter().chain(locals.1).collect();
+        path.target.resolve(&path.id, &vars, watched)
+    }
+
+    fn on_missing_in(&self, kit: Option<&KitConfig>, name: &str, bin: &LinkedBinary, reason: String, visited: &mut Vec<String>, watched: &mut Vec<Stamp>) -> Result<Resolution, Error> {
+        let policy = kit.and_then(|k| k.on_missing.as_ref())
+            .or_else(|| self.layers().into_iter().find_map(|l| l.on_missing()));
 
+        match policy {
+            // only the unset default degrades to `$PATH` outside strict mode
+            None => {
+                Err(format_err!("{}", reason))
+            }
+            Some(OnMissing::Error) => {
+                Err(MissingBinary(reason).into())
+            }
+            Some(OnMissing::FallbackToPath) => {
+                let warning = format!("{}, using $PATH", reason);
+                Ok(Resolution { target: None, warning: Some(warning), watched: Vec::new() })
+            }
+            Some(OnMissing::FallbackToKit { kit }) if visited.contains(kit) => {
+                Err(format_err!("{}, fallback kits form a cycle: {} -> {}", reason, visited.join(" -> "), kit))
+            }
+            Some(OnMissing::FallbackToKit { kit }) => {
+                log::debug!("{}, trying kit `{}`", reason, kit);
+                self.resolve_in_kit(name, bin, kit, visited, watched)
+            }
+        }
+    }
+}
+
+/// Bin sets every config can use, a `[binsets]` entry of the same name replaces one.
+pub const BUILTIN_BINSETS: [(&str, &[&str]); 5] = [
+    ("jdk", &[
+        "jar", "jarsigner", "java", "javac", "javadoc", "javap", "jcmd", "jconsole", "jdb", "jdeprscan", "jdeps", "jfr",
+        "jhsdb", "jimage", "jinfo", "jlink", "jmap", "jmod", "jpackage", "jps", "jrunscript", "jshell", "jstack", "jstat",
+        "jstatd", "jwebserver", "keytool", "rmiregistry", "serialver",
+    ]),
+    ("graal", &["gu", "js", "lli", "native-image", "polyglot"]),
+    ("node", &["corepack", "node", "npm", "npx"]),
+    ("scala", &["scala", "scalac", "scaladoc", "scalap"]),
+    ("sbt", &["sbt"]),
+];
+
+/// Bin sets visible to a config stack.
+pub struct BinSets<'a> {
+    /// highest priority first
+    defined: Vec<&'a BTreeMap<String, Vec<String>>>,
+}
+
+impl<'a> BinSets<'a> {
+    pub fn members(&self, set: &str) -> Option<Vec<&'a str>> {
+        match self.defined.iter().find_map(|d| d.get(set)) {
+            Some(members) => Some(members.iter().map(String::as_str).collect()),
+            None => BUILTIN_BINSETS.iter().find(|(name, _)| *name == set).map(|(_, members)| members.to_vec()),
+        }
+    }
 
-        ResolvedConfig { bins: out, names: out_n }
+    /// Every set name, defined or built in, sorted.
+    pub fn names(&self) -> Vec<&'a str> {
+        let defined = self.defined.iter().flat_map(|d| d.keys().map(String::as_str));
+        let builtin = BUILTIN_BINSETS.iter().map(|(name, _)| *name);
+        defined.chain(builtin).collect::<BTreeSet<_>>().into_iter().collect()
     }
 }
 
-trait AbstractConfig {
+impl LinkedBinary {
+    /// Whether this entry maps `name`, directly or through its set.
+    pub fn matches(&self, name: &str, sets: &BinSets) -> bool {
+        match &self.selector {
+            BinSelector::Name { name: own } => own == name,
+            BinSelector::Set { set } => sets.members(set).map(|m| m.contains(&name)).unwrap_or(false),
+        }
+    }
+}
+
+fn applies(when: &Option<Condition>, facts: &Facts) -> bool {
+    when.as_ref().map(|w| w.matches(facts)).unwrap_or(true)
+}
+
+pub trait AbstractConfig: std::fmt::Debug {
+    fn strict(&self) -> Option<bool>;
+    fn pin_files(&self) -> Option<bool>;
+    fn tool_kits(&self) -> &BTreeMap<String, String>;
+    fn binsets(&self) -> &BTreeMap<String, Vec<String>>;
     fn bins(&self) -> &Vec<LinkedBinary>;
     fn kits(&self) -> &Vec<KitConfig>;
     fn paths(&self) -> &Vec<KitPath>;
+    fn on_missing(&self) -> Option<&OnMissing>;
+
+    // later entries win, same as they would when collected into a map,
+    // except that an entry naming the binary beats any set containing it, keyed tables lose document order
+    fn find_bin(&self, name: &str, facts: &Facts, sets: &BinSets) -> Option<&LinkedBinary> {
+        let applicable = || self.bins().iter().rev().filter(|b| applies(&b.when, facts));
+        applicable().find(|b| matches!(&b.selector, BinSelector::Name { name: own } if own == name))
+            .or_else(|| applicable().find(|b| b.matches(name, sets)))
+    }
 
-    fn resolve(&self) -> HashMap<String, String> {
-        let paths: HashMap<String, String> = self.paths().iter().map(|p| {
-            let dir = match &p.target {
-                SdkTarget::Dir { path } => {
-                    String::from(path)
-                }
-                SdkTarget::HighestPrefix { base, prefix, path } => {
-                    let candidates: Vec<std::path::PathBuf> = std::fs::read_dir(base).expect(&format!("cannot read {:#?}", base))
-                        .map(|d| {
-                            d.expect(&format!("cannot extract path from {:#?}", "???")).path()
-                        })
-                        .filter(|e| {
-                            e
-                                .file_name().expect(&format!("cannot extract name from {:#?}", e)).
-                                to_str().expect(&format!("cannot convert file name: {:#?}", e))
-                                .starts_with(prefix)
-                        }).collect();
-
-                    let max = candidates.iter().max()
-                        .expect(&format!("cannot find best candidate: {:#?}", candidates))
-                        .to_str().expect(&format!("cannot convert to string: {:#?}", candidates));
-
-
-
-                    let out = format!("{}/{}", max, path);
-                    out
-                }
-            };
+    fn find_kit(&self, name: &str, facts: &Facts) -> Option<&KitConfig> {
+        self.kits().iter().rev().find(|k| k.name == name && applies(&k.when, facts))
+    }
 
-            (String::from(&p.id), dir)
-        }).collect();
+    fn find_path(&self, id: &str, facts: &Facts) -> Option<&KitPath> {
+        self.paths().iter().rev().find(|p| p.id == id && applies(&p.when, facts))
+    }
+}
 
-        let kits: HashMap<String, String> = self.kits().iter().flat_map(|kit| {
-            match paths.get(kit.id.as_str()) {
-                Some(path) => {
-                    [(String::from(&kit.name), String::from(path))].to_vec()
-                }
-                None => {
-                    Vec::new()
-                }
+impl SdkTarget {
+    fn resolve(&self, id: &str, vars: &Vars, watched: &mut Vec<Stamp>) -> Result<PathBuf, Error> {
+        match self {
+            SdkTarget::Dir { path } => {
+                expand(path, &format!("paths[{}].target.path", id), vars, watched)
             }
-        }).collect();
-
-
-        let bins: HashMap<String, String> = self.bins().iter().flat_map(|bin| {
-            match &bin.target {
-                LinkTarget::Sdk { name } => {
-                    match kits.get(name.as_str()) {
-                        Some(path) => {
-                            [(String::from(&bin.name), String::from(path))].to_vec()
-                        }
-                        None => {
-                            Vec::new()
-                        }
+            SdkTarget::HighestPrefix { base, prefix, path } => {
+                let base = expand(base, &format!("paths[{}].target.base", id), vars, watched)?;
+                // relative to the picked entry, not the config
+                let inner = Vars { relative_to: None, ..*vars };
+                let path = expand(path, &format!("paths[{}].target.path", id), &inner, watched)?;
+
+                // stamp before reading, so a concurrent change can only make the cached entry stale
+                watched.push(Stamp::of(&base));
+
+                let entries = std::fs::read_dir(&base)
+                    .map_err(|e| format_err!("cannot read {}: {}", base.display(), e))?;
+
+                let mut candidates = Vec::new();
+                for entry in entries {
+                    let entry = entry.map_err(|e| format_err!("cannot read {}: {}", base.display(), e))?;
+                    if entry.file_name().as_bytes().starts_with(prefix.as_bytes()) {
+                        candidates.push(entry.path());
                     }
                 }
-                LinkTarget::Default => {
-                    Vec::new()
-                }
+
+                let max = candidates.into_iter().max()
+                    .ok_or_else(|| format_err!("no entries starting with `{}` in {}", prefix, base.display()))?;
+
+                // always inside the picked entry, a leading `/` included
+                Ok(max.join(path.strip_prefix("/").unwrap_or(&path)))
+            }
+            SdkTarget::Sdkman { candidate, version } => {
+                let base = manager_root("SDKMAN_DIR", ".sdkman", watched)?.join("candidates").join(candidate);
+                Ok(latest_matching(&base, version, watched)?.join("bin"))
+            }
+            SdkTarget::Nvm { version } => {
+                let base = manager_root("NVM_DIR", ".nvm", watched)?.join("versions/node");
+                let version = format!("v{}", version.trim_start_matches('v'));
+                Ok(latest_matching(&base, &version, watched)?.join("bin"))
+            }
+            SdkTarget::Pyenv { version } => {
+                let base = manager_root("PYENV_ROOT", ".pyenv", watched)?.join("versions");
+                Ok(latest_matching(&base, version, watched)?.join("bin"))
+            }
+            SdkTarget::Jabba { name } => {
+                let base = manager_root("JABBA_HOME", ".jabba", watched)?.join("jdk");
+                // macOS installs are bundles
+                let bin = if vars.facts.os == "macos" { "Contents/Home/bin" } else { "bin" };
+                Ok(latest_matching(&base, name, watched)?.join(bin))
             }
-        }).collect();
+            SdkTarget::Asdf { plugin, version } => {
+                let base = manager_root("ASDF_DATA_DIR", ".asdf", watched)?.join("installs").join(plugin);
+                Ok(latest_matching(&base, version, watched)?.join("bin"))
+            }
+        }
+    }
+}
 
-        bins
+/// Where a version manager keeps its installs: `$var`, or `default` in the home directory.
+fn manager_root(var: &str, default: &str, watched: &mut Vec<Stamp>) -> Result<PathBuf, Error> {
+    watched.push(Stamp::var(var));
+    match std::env::var_os(var).filter(|v| !v.is_empty()) {
+        Some(dir) => Ok(PathBuf::from(dir)),
+        None => dirs::home_dir()
+            .map(|h| h.join(default))
+            .ok_or_else(|| format_err!("cannot locate ~/{}: no home directory", default)),
     }
 }
 
-impl AbstractConfig for LocalConfig {
-    fn bins(&self) -> &Vec<LinkedBinary> {
-        &self.bins
+/// Newest entry of `base` starting with `prefix`, compared as versions.
+///
+/// A prefix ending in a digit ends a number, `17` matches `17.0.5-tem` but not `171`.
+fn latest_matching(base: &Path, prefix: &str, watched: &mut Vec<Stamp>) -> Result<PathBuf, Error> {
+    watched.push(Stamp::of(base));
+
+    let entries = std::fs::read_dir(base)
+        .map_err(|e| format_err!("cannot read {}: {}", base.display(), e))?;
+
+    let mut candidates = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| format_err!("cannot read {}: {}", base.display(), e))?;
+        // the lossy name is only for matching, joining it would name a directory that isn't there
+        let file_name = entry.file_name();
+        let name = file_name.to_string_lossy().into_owned();
+        let rest = match name.strip_prefix(prefix) {
+            Some(rest) => rest,
+            None => continue,
+        };
+        let splits_number = prefix.ends_with(|c: char| c.is_ascii_digit()) && rest.starts_with(|c: char| c.is_ascii_digit());
+        // sdkman's `current` link is a version of its own
+        if !splits_number && (name != "current" || prefix == "current") {
+            candidates.push((name, file_name));
+        }
     }
 
-    fn kits(&self) -> &Vec<KitConfig> {
-        &self.kits
+    candidates.into_iter()
+        .max_by(|(a, _), (b, _)| version_cmp(a, b))
+        .map(|(_, file_name)| base.join(file_name))
+        .ok_or_else(|| format_err!("no version matching `{}` installed in {}", prefix, base.display()))
+}
+
+impl AbstractConfig for FileConfig {
+    fn strict(&self) -> Option<bool> {
+        self.strict
     }
 
-    fn paths(&self) -> &Vec<KitPath> {
-        &self.paths
+    fn pin_files(&self) -> Option<bool> {
+        self.pin_files
+    }
+
+    fn tool_kits(&self) -> &BTreeMap<String, String> {
+        &self.tool_kits
+    }
+
+    fn binsets(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.binsets
     }
-}
 
-impl AbstractConfig for GlobalConfig {
     fn bins(&self) -> &Vec<LinkedBinary> {
         &self.bins
     }
 
+    fn on_missing(&self) -> Option<&OnMissing> {
+        self.on_missing.as_ref()
+    }
+
     fn kits(&self) -> &Vec<KitConfig> {
         &self.kits
     }
@@ -141,169 +490,228 @@ impl AbstractConfig for GlobalConfig {
     }
 }
 
+/// One config file, any layer of the stack, merged with the files it includes.
+///
+/// Parsed through `RawConfig`, which documents the fields, so `bins` and `kits` may use the terse map form.
 #[derive(Serialize, Deserialize, Debug)]
-pub struct LocalConfig {
+#[serde(from = "RawConfig")]
+pub struct FileConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pin_files: Option<bool>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tool_kits: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub binsets: BTreeMap<String, Vec<String>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_missing: Option<OnMissing>,
     pub bins: Vec<LinkedBinary>,
     pub kits: Vec<KitConfig>,
     pub paths: Vec<KitPath>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct GlobalConfig {
-    pub bins: Vec<LinkedBinary>,
-    pub kits: Vec<KitConfig>,
-    pub paths: Vec<KitPath>,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
+/// Parsed through `RawConfig`'s `BinTable`, which documents the fields.
+#[derive(Serialize, Debug)]
 pub struct LinkedBinary {
-    pub name: String,
+    #[serde(flatten)]
+    pub selector: BinSelector,
     pub target: LinkTarget,
+    /// Executable to look up in the kit, defaults to `name`.
+    /// Lets several names share one multi-call binary (busybox etc).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exec: Option<String>,
+    /// What the launched binary sees as `argv[0]`, defaults to `Resolved`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub argv0: Option<Argv0>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<Condition>,
+    /// file declaring the entry, for errors about it
+    #[serde(skip)]
+    pub origin: Option<PathBuf>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(tag = "type")]
+/// The binaries a bins entry maps.
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum BinSelector {
+    Name { name: String },
+    /// every binary of a bin set
+    Set { set: String },
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+#[serde(tag = "type", deny_unknown_fields)]
+pub enum Argv0 {
+    /// `argv[0]` exactly as binlink was invoked, e.g. `java`
+    Invoked,
+    /// absolute path of the resolved binary
+    Resolved,
+    Custom { value: String },
+}
+
+impl Argv0 {
+    pub fn select(&self, invoked: &OsStr, resolved: &Path) -> OsString {
+        match self {
+            Argv0::Invoked => invoked.to_owned(),
+            Argv0::Resolved => resolved.as_os_str().to_owned(),
+            Argv0::Custom { value } => OsString::from(value),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[serde(tag = "type", deny_unknown_fields)]
 pub enum LinkTarget {
+    /// whatever `$PATH` provides, cancels mappings from lower layers
     Default,
     Sdk { name: String },
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct KitConfig {
     pub name: String,
     pub id: String,
+    /// overrides the config-wide `on_missing` for binaries mapped to this kit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_missing: Option<OnMissing>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<Condition>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(tag = "type")]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+#[serde(tag = "type", deny_unknown_fields)]
+pub enum OnMissing {
+    Error,
+    /// run whatever `$PATH` provides, with a warning
+    FallbackToPath,
+    /// look the binary up in another kit
+    FallbackToKit { kit: String },
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[serde(tag = "type", deny_unknown_fields)]
 pub enum SdkTarget {
     Dir { path: String },
     HighestPrefix { base: String, prefix: String, path: String },
+    /// `$SDKMAN_DIR/candidates/<candidate>`, `current` only when asked for
+    Sdkman { candidate: String, version: String },
+    /// `$NVM_DIR/versions/node`, `18` and `v18` alike
+    Nvm { version: String },
+    /// `$PYENV_ROOT/versions`
+    Pyenv { version: String },
+    /// `$JABBA_HOME/jdk`, e.g. `openjdk@1.17`
+    Jabba { name: String },
+    /// `$ASDF_DATA_DIR/installs/<plugin>`
+    Asdf { plugin: String, version: String },
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct KitPath {
     pub id: String,
     pub target: SdkTarget,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<Condition>,
+    /// file this entry was declared in
+    #[serde(skip)]
+    pub origin: Option<PathBuf>,
+    /// declared in a version 1 file, `path` and `base` are used as written
+    #[serde(skip)]
+    pub literal: bool,
 }
 
-impl LocalConfig {
-    pub fn graal_bins() -> Vec<String> {
-        let out = vec![
-            "native-image",
-            "gu",
-            "rebuild-images",
-            "polyglot",
-            "lli",
-        ];
-
-        let asvec = out.iter().map(|b| String::from(*b)).collect();
-        return asvec;
-    }
-
-    pub fn node_bins() -> Vec<String> {
-        let out = vec![
-            "js",
-            "node",
-            "npx",
-            "npm",
-        ];
-
-        let asvec = out.iter().map(|b| String::from(*b)).collect();
-        return asvec;
-    }
-
-    pub fn sbt_bins() -> Vec<String> {
-        let out = vec![
-            "sbt",
-        ];
-
-        let asvec = out.iter().map(|b| String::from(*b)).collect();
-        return asvec;
-    }
-
-    pub fn scala_bins() -> Vec<String> {
-        let out = vec![
-            "fsc",
-            "scala",
-            "scalac",
-            "scaladoc",
-            "scalap",
-        ];
-
-        let asvec = out.iter().map(|b| String::from(*b)).collect();
-        return asvec;
-    }
-
-
-    pub fn jdk_bins() -> Vec<String> {
-        let out = vec![
-            "jar",
-            "jarsigner",
-            "java",
-            "javac",
-            "javadoc",
-            "javap",
-            "jcmd",
-            "jconsole",
-            "jdb",
-            "jdeprscan",
-            "jdeps",
-            "jfr",
-            "jhsdb",
-            "jimage",
-            "jinfo",
-            "jjs",
-            "jlink",
-            "jmap",
-            "jmod",
-            "jps",
-            "jrunscript",
-            "jshell",
-            "jstack",
-            "jstat",
-            "jstatd",
-            "jvisualvm",
-            "keytool",
-            "pack200",
-            "rmic",
-            "rmid",
-            "rmiregistry",
-            "serialver",
-            "unpack200",
-        ];
-        return out.iter().map(|b| String::from(*b)).collect();
-    }
-
-    // pub fn example() -> LocalConfig {
-    //     let example = LocalConfig {
-    //         bins: vec![],
-    //         kits: vec![],
-    //         paths: vec![],
-    //     };
-    //     return example;
-    // }
+impl KitPath {
+    /// Notice for a version 1 path which looks like it expects `~`, `${VAR}` or `{config_dir}` to be expanded.
+    fn literal_note(&self) -> Option<String> {
+        let fields = match &self.target {
+            SdkTarget::Dir { path } => vec![path],
+            SdkTarget::HighestPrefix { base, path, .. } => vec![base, path],
+            _ => Vec::new(),
+        };
+        let expects_expansion = fields.iter().any(|f| f.starts_with('~') || f.contains('{'));
+
+        match &self.origin {
+            Some(origin) if self.literal && expects_expansion => Some(format!(
+                "path `{}` is used as written, {} is config version 1, set `version = 2` or run `binlink migrate`",
+                self.id, origin.display(),
+            )),
+            _ => None,
+        }
+    }
 }
 
-impl GlobalConfig {
-    pub fn example() -> GlobalConfig {
-        let bins: Vec<(String, String)> = [
-            LocalConfig::graal_bins().iter().map(|bin| (String::from(bin), String::from("jdk-graal"))).collect::<Vec<(String, String)>>(),
-            LocalConfig::jdk_bins().iter().map(|bin| (String::from(bin), String::from("jdk"))).collect::<Vec<(String, String)>>(),
-            LocalConfig::node_bins().iter().map(|bin| (String::from(bin), String::from("node"))).collect::<Vec<(String, String)>>(),
-            LocalConfig::scala_bins().iter().map(|bin| (String::from(bin), String::from("scala"))).collect::<Vec<(String, String)>>(),
-            LocalConfig::sbt_bins().iter().map(|bin| (String::from(bin), String::from("sbt"))).collect::<Vec<(String, String)>>(),
-        ]
-            .concat();
+impl FileConfig {
+    pub fn empty() -> FileConfig {
+        FileConfig {
+            version: None,
+            strict: None,
+            pin_files: None,
+            tool_kits: BTreeMap::new(),
+            binsets: BTreeMap::new(),
+            include: Vec::new(),
+            on_missing: None,
+            bins: Vec::new(),
+            kits: Vec::new(),
+            paths: Vec::new(),
+        }
+    }
 
-        let bins = bins.into_iter().map(|(name, sdk)| LinkedBinary { name: name, target: LinkTarget::Sdk { name: sdk } }).collect();
+    /// Remembers which file declared the entries, for `{config_dir}`, the format version of paths and errors.
+    pub fn set_origin(&mut self, origin: &Path) {
+        let literal = self.version.unwrap_or(1) < 2;
+        self.bins.iter_mut().for_each(|b| b.origin = Some(origin.to_path_buf()));
+        self.paths.iter_mut().for_each(|p| {
+            p.origin = Some(origin.to_path_buf());
+            p.literal = literal;
+        });
+    }
 
+    /// Puts `self` on top of `lower`, later entries win so `self` goes last.
+    pub fn overlay(self, lower: FileConfig) -> FileConfig {
+        FileConfig {
+            version: self.version,
+            strict: self.strict.or(lower.strict),
+            pin_files: self.pin_files.or(lower.pin_files),
+            tool_kits: lower.tool_kits.into_iter().chain(self.tool_kits).collect(),
+            binsets: lower.binsets.into_iter().chain(self.binsets).collect(),
+            include: self.include,
+            on_missing: self.on_missing.or(lower.on_missing),
+            bins: lower.bins.into_iter().chain(self.bins).collect(),
+            kits: lower.kits.into_iter().chain(self.kits).collect(),
+            paths: lower.paths.into_iter().chain(self.paths).collect(),
+        }
+    }
 
-        let example = GlobalConfig {
-            bins: bins,
+    pub fn example() -> FileConfig {
+        let bins = [("graal", "jdk-graal"), ("jdk", "jdk"), ("node", "node"), ("scala", "scala"), ("sbt", "sbt")].iter()
+            .map(|(set, kit)| LinkedBinary {
+                selector: BinSelector::Set { set: String::from(*set) },
+                target: LinkTarget::Sdk { name: String::from(*kit) },
+                exec: None,
+                argv0: None,
+                when: None,
+                origin: None,
+            })
+            .collect();
+
+        FileConfig {
+            version: Some(CONFIG_VERSION),
+            strict: None,
+            pin_files: None,
+            tool_kits: BTreeMap::new(),
+            binsets: BTreeMap::new(),
+            include: Vec::new(),
+            on_missing: None,
+            bins,
             kits: vec![
-                KitConfig { name: String::from("jdk"), id: String::from("graalvm") },
-                KitConfig { name: String::from("jdk-graal"), id: String::from("graalvm") },
+                KitConfig { name: String::from("jdk"), id: String::from("graalvm"), on_missing: None, when: None },
+                KitConfig { name: String::from("jdk-graal"), id: String::from("graalvm"), on_missing: None, when: None },
             ],
             paths: vec![
                 //KitPath { id: String::from("graalvm"), target: SdkTarget::Dir { path: String::from("/Library/Java/JavaVirtualMachines/graalvm-ce-java11-20.2.0/Contents/Home/bin/") } },
@@ -314,9 +722,269 @@ impl GlobalConfig {
                         prefix: String::from("graalvm-ce-java11"),
                         path: String::from("Contents/Home/bin/"),
                     },
+                    when: None,
+                    origin: None,
+                    literal: false,
                 },
             ],
-        };
-        return example;
+        }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{executables, mkdirs, temp_dir};
+
+    /// Layers lowest priority first, each as if read from its own file in `dir`.
+    fn stack(dir: &Path, layers: &[(LayerKind, &str)]) -> Config {
+        let layers = layers.iter().enumerate()
+            .map(|(idx, (kind, content))| {
+                let path = dir.join(format!("layer{}.toml", idx));
+                let mut config: FileConfig = toml::from_str(content).unwrap_or_else(|e| panic!("{}", e));
+                config.set_origin(&path);
+                Layer { kind: *kind, path, includes: Vec::new(), watched: Vec::new(), config: Box::new(config) }
+            })
+            .collect();
+        Config { layers, facts: Facts::detect() }
+    }
+
+    fn resolved(config: &Config, name: &str) -> Result<Option<PathBuf>, String> {
+        config.resolve(name).map(|r| r.target.map(|t| t.path)).map_err(|e| e.to_string())
+    }
+
+    fn latest(base: &Path, prefix: &str) -> Result<String, String> {
+        latest_matching(base, prefix, &mut Vec::new())
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .map_err(|e| e.to_string())
+    }
+
+    fn kit_of<'a>(config: &'a FileConfig, name: &str) -> Option<&'a str> {
+        let sets = BinSets { defined: vec![config.binsets()] };
+        config.find_bin(name, &Facts::detect(), &sets).map(|b| match &b.target {
+            LinkTarget::Sdk { name } => name.as_str(),
+            LinkTarget::Default => "",
+        })
+    }
+
+    #[test]
+    fn names_beat_sets_in_any_order() {
+        let keyed: FileConfig = toml::from_str("[bins]\njava = \"jdk21\"\njdk = { set = \"jdk\", kit = \"jdk17\" }\n").unwrap();
+        assert_eq!(kit_of(&keyed, "java"), Some("jdk21"));
+        assert_eq!(kit_of(&keyed, "javac"), Some("jdk17"));
+
+        let listed: FileConfig = toml::from_str(r#"
+bins = [ { name = "java", kit = "jdk21" }, { set = "jdk", kit = "jdk17" }, { set = "jdk", kit = "jdk11" } ]
+"#).unwrap();
+        assert_eq!(kit_of(&listed, "java"), Some("jdk21"));
+        assert_eq!(kit_of(&listed, "javac"), Some("jdk11"));
+        assert_eq!(kit_of(&listed, "node"), None);
+    }
+
+    #[test]
+    fn matches_whole_numbers_only() {
+        let base = temp_dir("latest-numbers");
+        mkdirs(&base, &["17.0.5-tem", "171", "1.17"]);
+        assert_eq!(latest(&base, "17"), Ok(String::from("17.0.5-tem")));
+        assert_eq!(latest(&base, "171"), Ok(String::from("171")));
+        assert_eq!(latest(&base, "17.0"), Ok(String::from("17.0.5-tem")));
+        assert_eq!(latest(&base, "1"), Ok(String::from("1.17")));
+        assert!(latest(&base, "2").unwrap_err().contains("no version matching `2`"));
+    }
+
+    #[test]
+    fn picks_highest_version() {
+        let base = temp_dir("latest-highest");
+        mkdirs(&base, &["17.0.5-tem", "17.0.12-tem", "17.0.9-tem", "21.0.1-tem"]);
+        assert_eq!(latest(&base, "17"), Ok(String::from("17.0.12-tem")));
+        assert_eq!(latest(&base, ""), Ok(String::from("21.0.1-tem")));
+    }
+
+    #[test]
+    fn skips_current_unless_asked() {
+        let base = temp_dir("latest-current");
+        mkdirs(&base, &["current", "11.0.2-open"]);
+        assert_eq!(latest(&base, ""), Ok(String::from("11.0.2-open")));
+        assert_eq!(latest(&base, "c"), Err(format!("no version matching `c` installed in {}", base.display())));
+        assert_eq!(latest(&base, "current"), Ok(String::from("current")));
+    }
+
+    #[test]
+    fn joins_names_which_are_not_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let base = temp_dir("latest-non-utf8");
+        let name = std::ffi::OsStr::from_bytes(b"17.0.\xff");
+        std::fs::create_dir(base.join(name)).unwrap();
+        mkdirs(&base, &["17.0"]);
+
+        let latest = latest_matching(&base, "17", &mut Vec::new()).unwrap();
+        assert_eq!(latest, base.join(name));
+        assert!(latest.is_dir());
+    }
+
+    #[test]
+    fn watches_base() {
+        let base = temp_dir("latest-watched");
+        let mut watched = Vec::new();
+        assert!(latest_matching(&base, "17", &mut watched).is_err());
+        assert_eq!(watched.len(), 1);
+    }
+
+    #[test]
+    fn handles_missing_binaries_per_kit_before_config_wide() {
+        let dir = temp_dir("on-missing");
+        executables(&dir, &["a/bin/java", "b/bin/javac", "d/bin/jshell"]);
+        mkdirs(&dir, &["c/bin"]);
+        let config = format!(r#"
+version = 2
+on_missing = {{ type = "FallbackToPath" }}
+bins = [
+  {{ name = "java", kit = "a" }}, {{ name = "javac", kit = "a" }}, {{ name = "jshell", kit = "a" }},
+  {{ name = "jar", kit = "c" }}, {{ name = "jdb", kit = "loop1" }}, {{ name = "javap", kit = "strict" }},
+]
+kits = [
+  {{ name = "a", id = "a", on_missing = {{ type = "FallbackToKit", kit = "b" }} }},
+  {{ name = "b", id = "b", on_missing = {{ type = "FallbackToKit", kit = "d" }} }},
+  {{ name = "c", id = "c" }},
+  {{ name = "d", id = "d" }},
+  {{ name = "loop1", id = "c", on_missing = {{ type = "FallbackToKit", kit = "loop2" }} }},
+  {{ name = "loop2", id = "c", on_missing = {{ type = "FallbackToKit", kit = "loop1" }} }},
+  {{ name = "strict", id = "c", on_missing = {{ type = "Error" }} }},
+]
+paths = [
+  {{ id = "a", target = {{ type = "Dir", path = "{dir}/a/bin" }} }},
+  {{ id = "b", target = {{ type = "Dir", path = "{dir}/b/bin" }} }},
+  {{ id = "c", target = {{ type = "Dir", path = "{dir}/c/bin" }} }},
+  {{ id = "d", target = {{ type = "Dir", path = "{dir}/d/bin" }} }},
+]
+"#, dir = dir.display());
+        let config = stack(&dir, &[(LayerKind::User, &config)]);
+
+        assert_eq!(resolved(&config, "java"), Ok(Some(dir.join("a/bin/java"))));
+        // the kit's own policy wins over the config-wide one, fallback kits chain
+        assert_eq!(resolved(&config, "javac"), Ok(Some(dir.join("b/bin/javac"))));
+        assert_eq!(resolved(&config, "jshell"), Ok(Some(dir.join("d/bin/jshell"))));
+
+        let jar = config.resolve("jar").unwrap();
+        assert!(jar.target.is_none());
+        assert_eq!(jar.warning, Some(format!("kit `c` has no `jar` in {}, using $PATH", dir.join("c/bin").display())));
+
+        let cycle = resolved(&config, "jdb").unwrap_err();
+        assert!(cycle.ends_with("fallback kits form a cycle: loop1 -> loop2 -> loop1"), "{}", cycle);
+
+        let strict = config.resolve("javap").unwrap_err();
+        assert!(strict.downcast_ref::<MissingBinary>().is_some(), "{}", strict);
+    }
+
+    #[test]
+    fn fails_softly_without_on_missing() {
+        let dir = temp_dir("on-missing-unset");
+        mkdirs(&dir, &["c/bin"]);
+        let config = format!(r#"
+version = 2
+bins = [ {{ name = "jar", kit = "c" }}, {{ name = "jdb", kit = "nowhere" }} ]
+kits = [ {{ name = "c", id = "c" }} ]
+paths = [ {{ id = "c", target = {{ type = "Dir", path = "{}/c/bin" }} }} ]
+"#, dir.display());
+        let config = stack(&dir, &[(LayerKind::User, &config)]);
+
+        let missing = config.resolve("jar").unwrap_err();
+        assert!(missing.downcast_ref::<MissingBinary>().is_none());
+        assert_eq!(missing.to_string(), format!("kit `c` has no `jar` in {}", dir.join("c/bin").display()));
+        assert_eq!(resolved(&config, "jdb"), Err(String::from("`jdb` points to undefined kit `nowhere`")));
+    }
+
+    #[test]
+    fn default_in_a_higher_layer_cancels_a_mapping() {
+        let dir = temp_dir("default-cancels");
+        executables(&dir, &["jdk/bin/java", "jdk/bin/javac"]);
+        let user = format!(r#"
+version = 2
+bins = [ {{ set = "jdk", kit = "jdk" }} ]
+kits = [ {{ name = "jdk", id = "jdk" }} ]
+paths = [ {{ id = "jdk", target = {{ type = "Dir", path = "{}/jdk/bin" }} }} ]
+"#, dir.display());
+        let project = "version = 2\nbins = [ { name = \"java\", target = { type = \"Default\" } } ]\n";
+        let config = stack(&dir, &[(LayerKind::User, &user), (LayerKind::Project, project)]);
+
+        let java = config.resolve("java").unwrap();
+        assert!(java.target.is_none() && java.warning.is_none(), "{:?}", java);
+        assert_eq!(config.origin_of("java").map(|l| l.kind), Some(LayerKind::Project));
+        assert_eq!(resolved(&config, "javac"), Ok(Some(dir.join("jdk/bin/javac"))));
+    }
+
+    #[test]
+    fn resolves_project_bins_through_user_kits() {
+        let dir = temp_dir("project-through-user");
+        executables(&dir, &["jdk17/bin/java", "jdk21/bin/java"]);
+        let user = format!(r#"
+version = 2
+kits = [ {{ name = "jdk17", id = "temurin-17" }}, {{ name = "jdk21", id = "temurin-21" }} ]
+paths = [
+  {{ id = "temurin-17", target = {{ type = "Dir", path = "{dir}/jdk17/bin" }} }},
+  {{ id = "temurin-21", target = {{ type = "Dir", path = "{dir}/jdk21/bin" }} }},
+]
+"#, dir = dir.display());
+        let project = "version = 2\n[bins]\njava = \"jdk17\"\n";
+        let config = stack(&dir, &[(LayerKind::User, &user), (LayerKind::Project, project)]);
+
+        assert_eq!(config.origin_of("java").map(|l| l.kind), Some(LayerKind::Project));
+        assert_eq!(resolved(&config, "java"), Ok(Some(dir.join("jdk17/bin/java"))));
+        assert_eq!(config.kit_dir("jdk21").unwrap(), dir.join("jdk21/bin"));
+    }
+
+    #[test]
+    fn skips_entries_whose_condition_fails() {
+        let config: FileConfig = toml::from_str(r#"
+version = 2
+bins = [ { name = "java", kit = "jdk-mac", when = { os = "macos" } }, { name = "java", kit = "jdk-linux", when = { os = "linux" } } ]
+kits = [ { name = "jdk", id = "ci", when = { hostname = "ci-*" } }, { name = "jdk", id = "local", when = { hostname = "dev-*" } } ]
+paths = [ { id = "ci", target = { type = "Dir", path = "/opt/jdk" } }, { id = "ci", target = { type = "Dir", path = "/usr/jdk" }, when = { arch = "x86_64" } } ]
+"#).unwrap();
+        let facts = |os: &str, arch: &str, hostname: &str| Facts { os: String::from(os), arch: String::from(arch), hostname: String::from(hostname) };
+        let sets = BinSets { defined: vec![config.binsets()] };
+        let kit_of = |facts: &Facts| config.find_bin("java", facts, &sets).map(|b| format!("{:?}", b.target));
+
+        assert_eq!(kit_of(&facts("linux", "x86_64", "ci-1")), Some(String::from("Sdk { name: \"jdk-linux\" }")));
+        assert_eq!(kit_of(&facts("macos", "x86_64", "ci-1")), Some(String::from("Sdk { name: \"jdk-mac\" }")));
+        assert_eq!(kit_of(&facts("windows", "x86_64", "ci-1")), None);
+
+        assert_eq!(config.find_kit("jdk", &facts("linux", "x86_64", "ci-1")).map(|k| k.id.as_str()), Some("ci"));
+        assert_eq!(config.find_kit("jdk", &facts("linux", "x86_64", "dev-2")).map(|k| k.id.as_str()), Some("local"));
+        assert!(config.find_kit("jdk", &facts("linux", "x86_64", "laptop")).is_none());
+
+        let path_of = |arch: &str| config.find_path("ci", &facts("linux", arch, "ci-1")).map(|p| format!("{:?}", p.target));
+        assert_eq!(path_of("x86_64"), Some(String::from("Dir { path: \"/usr/jdk\" }")));
+        assert_eq!(path_of("aarch64"), Some(String::from("Dir { path: \"/opt/jdk\" }")));
+    }
+
+    #[test]
+    fn warns_about_version_1_paths_expecting_expansion() {
+        let dir = temp_dir("literal-note");
+        executables(&dir, &["jdk/bin/java"]);
+        let config = format!(r#"
+bins = [ {{ name = "java", kit = "jdk" }}, {{ name = "javac", kit = "home" }}, {{ name = "jar", kit = "plain" }} ]
+kits = [ {{ name = "jdk", id = "jdk" }}, {{ name = "home", id = "home" }}, {{ name = "plain", id = "plain" }} ]
+paths = [
+  {{ id = "jdk", target = {{ type = "Dir", path = "{dir}/{{os}}/../jdk/bin" }} }},
+  {{ id = "home", target = {{ type = "Dir", path = "~/.binlink-test-nowhere/bin" }} }},
+  {{ id = "plain", target = {{ type = "Dir", path = "{dir}/jdk/bin" }} }},
+]
+"#, dir = dir.display());
+        let config = stack(&dir, &[(LayerKind::User, &config)]);
+        let note = |id: &str| format!("path `{}` is used as written, {} is config version 1", id, dir.join("layer0.toml").display());
+
+        let missing = resolved(&config, "javac").unwrap_err();
+        assert!(missing.contains("has no `javac` in ~/.binlink-test-nowhere/bin; "), "{}", missing);
+        assert!(missing.contains(&note("home")), "{}", missing);
+        let plain = config.resolve("jar").unwrap_err().to_string();
+        assert!(!plain.contains("config version 1"), "{}", plain);
+
+        // `{os}` taken literally may still exist, the warning comes with the result then
+        std::fs::create_dir_all(dir.join("{os}")).unwrap();
+        let java = config.resolve("java").unwrap();
+        assert!(java.target.is_some());
+        assert!(java.warning.unwrap().starts_with(&note("jdk")));
+    }
+}
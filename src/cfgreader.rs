@@ -1,76 +1,375 @@
 use std::env;
-use std::fs::File;
+use std::ffi::OsStr;
+use std::fs::{self, File};
 use std::io::Read;
 use std::path::{Path, PathBuf};
 
+use failure::{format_err, Error};
 use serde::de::DeserializeOwned;
 
-use crate::cfg::{Config, GlobalConfig, LocalConfig};
+use crate::cache::Stamp;
+use crate::cond::Facts;
+use crate::diag::parse_error;
+use crate::interp::{expand, Vars};
+use crate::pins;
+use crate::cfg::{BinSelector, Config, FileConfig, Layer, LayerKind};
 
 pub const LOCAL_CONFIG_DEFAULT_NAME: &str = ".binlink.toml";
+pub const LOCAL_OVERLAY_DEFAULT_NAME: &str = ".binlink.local.toml";
 pub const BASE_CONFIG_DEFAULT_NAME: &str = "binlink.toml";
+pub const SYSTEM_CONFIG_DEFAULT_PATH: &str = "/etc/binlink/binlink.toml";
 pub const BINLINK_LOCAL_CONFIG_NAME_EVAR: &str = "BINLINK_LOCAL_CONFIG_NAME";
 pub const BINLINK_BASE_CONFIG_PATH_EVAR: &str = "BINLINK_BASE_CONFIG_PATH";
+pub const BINLINK_SYSTEM_CONFIG_PATH_EVAR: &str = "BINLINK_SYSTEM_CONFIG_PATH";
+pub const BINLINK_STRICT_EVAR: &str = "BINLINK_STRICT";
+pub const BINLINK_DISABLE_EVAR: &str = "BINLINK_DISABLE";
+pub const BINLINK_PIN_FILES_EVAR: &str = "BINLINK_PIN_FILES";
 
-pub fn get_config() -> Config {
-    let parsed_local = parse_local_config();
-    let parsed_global = parse_base_config();
+/// Config file formats, picked by file extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl Format {
+    /// Lookup order when several files differ only in extension.
+    pub const EXTENSIONS: [&'static str; 4] = ["toml", "yaml", "yml", "json"];
+
+    pub fn of(path: &Path) -> Format {
+        match path.extension().and_then(OsStr::to_str) {
+            Some("yaml") | Some("yml") => Format::Yaml,
+            Some("json") => Format::Json,
+            _ => Format::Toml,
+        }
+    }
 
-    Config {
-        local: parsed_local,
-        global: parsed_global,
+    pub fn parse(name: &str) -> Option<Format> {
+        match name {
+            "toml" => Some(Format::Toml),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
     }
 }
 
-fn parse_base_config() -> Option<GlobalConfig> {
-    let baseconfig = read_base_config();
+pub struct ConfigSource {
+    pub kind: LayerKind,
+    pub path: PathBuf,
+}
+
+/// Existing config files, lowest priority first.
+pub struct ConfigPaths {
+    pub sources: Vec<ConfigSource>,
+}
+
+impl ConfigPaths {
+    pub fn all(&self) -> Vec<&PathBuf> {
+        self.sources.iter().map(|s| &s.path).collect()
+    }
+
+    /// Directory of the innermost project config.
+    pub fn project_root(&self) -> Option<&Path> {
+        self.sources.iter().rev()
+            .find(|s| s.kind == LayerKind::Project)
+            .and_then(|s| s.path.parent())
+    }
+}
+
+pub fn get_config() -> Result<Config, Error> {
+    parse_config(&get_config_paths()?)
+}
+
+/// System, user, then project configs from the outermost directory down to cwd,
+/// then per-user overlays in the same order.
+///
+/// Version files of other managers go right below the project config of their directory,
+/// except for `.tool-versions` they only take effect with `pin_files`, see `parse_config`.
+pub fn get_config_paths() -> Result<ConfigPaths, Error> {
+    let cwd = env::current_dir().map_err(|e| format_err!("cannot determine current directory: {}", e))?;
+    // outermost first, so configs closer to cwd win
+    let mut chain: Vec<&Path> = cwd.ancestors().collect();
+    chain.reverse();
+
+    let local_config_name = env::var_os(BINLINK_LOCAL_CONFIG_NAME_EVAR);
+
+    let system = read_system_config().map(|path| ConfigSource { kind: LayerKind::System, path });
+    let user = read_base_config().map(|path| ConfigSource { kind: LayerKind::User, path });
+    let projects = chain.iter().flat_map(|dir| {
+        let pins = pins::PIN_FILES.iter()
+            .filter_map(|name| maybe_config_in(Some(dir.to_path_buf()), name))
+            .map(|path| ConfigSource { kind: LayerKind::Pins, path });
+        // an explicit name is used as is, the default one in any format
+        let project = match &local_config_name {
+            Some(name) => maybe_config_in(Some(dir.to_path_buf()), name),
+            None => maybe_config_any(dir, LOCAL_CONFIG_DEFAULT_NAME),
+        };
+        pins.chain(project.map(|path| ConfigSource { kind: LayerKind::Project, path })).collect::<Vec<_>>()
+    });
+    let overlays = chain.iter()
+        .filter_map(|dir| maybe_config_any(dir, LOCAL_OVERLAY_DEFAULT_NAME))
+        .map(|path| ConfigSource { kind: LayerKind::Overlay, path });
+
+    let sources = system.into_iter()
+        .chain(user)
+        .chain(projects)
+        .chain(overlays)
+        .collect();
+
+    Ok(ConfigPaths { sources })
+}
+
+pub fn parse_config(paths: &ConfigPaths) -> Result<Config, Error> {
+    let layers = paths.sources.iter()
+        .map(|s| parse_layer(s, paths.project_root()))
+        .collect::<Result<Vec<Layer>, Error>>()?;
 
-    let parsed_global: Option<GlobalConfig> = match baseconfig {
-        Some(c) => {
-            Some(parse(c.as_path()))
+    // pins name versions, the layers below them know where those live;
+    // ignored ones stay as empty layers, their stamps keep cached results in sync with `BINLINK_PIN_FILES`
+    let read_all = reads_pin_files(&layers);
+    let mut config = Config { layers: Vec::new(), facts: Facts::detect() };
+    for mut layer in layers {
+        if layer.kind == LayerKind::Pins && (read_all || pins::always_read(&layer.path)) {
+            layer.config = Box::new(pins::layer_config(&pins::read(&layer.path), &config));
         }
-        None => { None }
+        config.layers.push(layer);
+    }
+
+    check_binsets(&config)?;
+    Ok(config)
+}
+
+/// Rejects bins mapping a set defined nowhere, sets are looked up through the whole stack so this waits for it.
+fn check_binsets(config: &Config) -> Result<(), Error> {
+    let sets = config.binsets();
+    let unknown = config.layers.iter()
+        .flat_map(|l| l.config.bins())
+        .find_map(|b| match &b.selector {
+            BinSelector::Set { set } if sets.members(set).is_none() => Some((set, b.origin.as_deref())),
+            _ => None,
+        });
+
+    let (set, origin) = match unknown {
+        Some((set, Some(origin))) => (set, origin),
+        Some((set, None)) => return Err(format_err!("unknown bin set `{}`", set)),
+        None => return Ok(()),
+    };
+
+    let expected: Vec<String> = sets.names().iter().map(|n| format!("`{}`", n)).collect();
+    let message = format!("unknown bin set `{}`, expected one of {}", set, expected.join(", "));
+    let content = fs::read_to_string(origin)
+        .map_err(|e| format_err!("cannot read config {}: {}", origin.display(), e))?;
+    Err(parse_error(origin, &content, &message, locate_set(&content, set)))
+}
+
+/// 1-based position of the value in `set = "name"`, `set: name` or `"set": "name"`, which the parser doesn't keep.
+fn locate_set(content: &str, set: &str) -> Option<(usize, usize)> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+    content.lines().enumerate().find_map(|(idx, line)| {
+        line.match_indices("set").find_map(|(start, _)| {
+            if line[..start].ends_with(is_word) {
+                return None;
+            }
+            let after_key = line[start + 3..].trim_start_matches(['"', '\'']).trim_start();
+            let value = after_key.strip_prefix(['=', ':'])?.trim_start();
+            let name = value.trim_start_matches(['"', '\'']);
+            if !name.starts_with(set) || name[set.len()..].starts_with(is_word) {
+                return None;
+            }
+            let column = line.len() - value.len();
+            Some((idx + 1, line[..column].chars().count() + 1))
+        })
+    })
+}
+
+/// Whether version files are honoured, like `strict` the topmost config setting it wins.
+pub fn reads_pin_files(layers: &[Layer]) -> bool {
+    env::var_os(BINLINK_PIN_FILES_EVAR).is_some()
+        || layers.iter().rev().find_map(|l| l.config.pin_files()).unwrap_or(false)
+}
+
+pub fn parse_layer(source: &ConfigSource, project_root: Option<&Path>) -> Result<Layer, Error> {
+    let mut included = Included {
+        stack: Vec::new(),
+        project_root: project_root.map(Path::to_path_buf),
+        files: Vec::new(),
+        watched: Vec::new(),
     };
-    parsed_global
+
+    let config = if source.kind == LayerKind::Pins {
+        // resolved against the whole stack in `parse_config`
+        included.watched.push(Stamp::var(BINLINK_PIN_FILES_EVAR));
+        FileConfig::empty()
+    } else {
+        load(&source.path, &mut included)?
+    };
+
+    Ok(Layer {
+        kind: source.kind,
+        path: source.path.clone(),
+        includes: included.files,
+        watched: included.watched,
+        config: Box::new(config),
+    })
+}
+
+struct Included {
+    /// files currently being loaded, for cycle detection
+    stack: Vec<PathBuf>,
+    /// directory of the innermost project config, for `{project_root}`
+    project_root: Option<PathBuf>,
+    files: Vec<PathBuf>,
+    watched: Vec<Stamp>,
 }
 
-fn parse_local_config() -> Option<LocalConfig> {
-    let localconfig = read_local_config();
+/// Parses `path` and everything it includes into one config.
+///
+/// Includes are merged in the listed order, glob matches sorted by name,
+/// and the including file goes on top of all of them.
+fn load(path: &Path, included: &mut Included) -> Result<FileConfig, Error> {
+    let canonical = path.canonicalize()
+        .map_err(|e| format_err!("cannot open config {}: {}", path.display(), e))?;
+
+    if included.stack.contains(&canonical) {
+        let chain: Vec<String> = included.stack.iter().chain(Some(&canonical)).map(|p| p.display().to_string()).collect();
+        return Err(format_err!("config include cycle: {}", chain.join(" -> ")));
+    }
+
+    let mut own: FileConfig = parse(path)?;
+    own.set_origin(path);
+    let base = path.parent().unwrap_or_else(|| Path::new("/"));
 
-    let parsed_local: Option<LocalConfig> = match localconfig {
-        Some(c) => {
-            Some(parse(c.as_path()))
+    included.stack.push(canonical);
+    let mut merged: Option<FileConfig> = None;
+    for pattern in &own.include {
+        for file in expand_include(pattern, base, included.project_root.as_deref(), &mut included.watched)? {
+            included.watched.push(Stamp::of(&file));
+            included.files.push(file.clone());
+            let lower = load(&file, included)?;
+            merged = Some(match merged {
+                Some(m) => lower.overlay(m),
+                None => lower,
+            });
         }
-        None => { None }
+    }
+    included.stack.pop();
+
+    Ok(match merged {
+        Some(m) => own.overlay(m),
+        None => own,
+    })
+}
+
+fn expand_include(pattern: &str, base: &Path, project_root: Option<&Path>, watched: &mut Vec<Stamp>) -> Result<Vec<PathBuf>, Error> {
+    let facts = Facts::detect();
+    let vars = Vars { config_dir: Some(base), project_root, facts: &facts, relative_to: Some(base), literal: false };
+    let path = expand(pattern, "include", &vars, watched)?;
+
+    if !pattern.contains(['*', '?', '[']) {
+        return Ok(vec![path]);
+    }
+
+    // files appearing in or vanishing from the globbed directory must invalidate cached results
+    let dir: PathBuf = path.ancestors()
+        .find(|a| !a.to_string_lossy().contains(['*', '?', '[']))
+        .unwrap_or_else(|| Path::new("/"))
+        .to_path_buf();
+    watched.push(Stamp::of(&dir));
+
+    let glob_pattern = path.to_str()
+        .ok_or_else(|| format_err!("cannot expand `{}`: {:?} is not valid UTF-8", pattern, path))?;
+    let mut files = glob::glob(glob_pattern)
+        .map_err(|e| format_err!("invalid include pattern `{}`: {}", pattern, e))?
+        .collect::<Result<Vec<PathBuf>, _>>()
+        .map_err(|e| format_err!("cannot expand `{}`: {}", pattern, e))?;
+    files.sort();
+
+    Ok(files)
+}
+
+/// Whether resolution errors must stay fatal instead of falling back to `$PATH`.
+///
+/// Only consulted after a failure, so every config which still parses gets its say.
+pub fn is_strict() -> bool {
+    if env::var_os(BINLINK_STRICT_EVAR).is_some() {
+        return true;
+    }
+
+    let paths = match get_config_paths() {
+        Ok(paths) => paths,
+        Err(_) => return false,
     };
-    parsed_local
+
+    paths.sources.iter().rev()
+        .filter_map(|s| parse_layer(s, paths.project_root()).ok())
+        .find_map(|l| l.config.strict())
+        .unwrap_or(false)
+}
+
+pub fn is_disabled() -> bool {
+    env::var_os(BINLINK_DISABLE_EVAR).is_some()
+}
+
+fn read_system_config() -> Option<PathBuf> {
+    match env::var_os(BINLINK_SYSTEM_CONFIG_PATH_EVAR) {
+        Some(path) => maybe_config_path(Some(PathBuf::from(path))),
+        None => {
+            let default = Path::new(SYSTEM_CONFIG_DEFAULT_PATH);
+            maybe_config_any(default.parent()?, default.file_name()?)
+        }
+    }
 }
 
 fn read_base_config() -> Option<PathBuf> {
-    let baseconfig = match env::var(BINLINK_BASE_CONFIG_PATH_EVAR) {
-        Ok(val) => {
-            maybe_config_path(Some(PathBuf::from(val.as_str())))
-        },
-        Err(_) => {
-            let home = dirs::home_dir().map(|h| h.join(".config").join("binlink"));
-            maybe_config_in(home, BASE_CONFIG_DEFAULT_NAME)
-        },
-    };
-    baseconfig
+    match env::var_os(BINLINK_BASE_CONFIG_PATH_EVAR) {
+        Some(val) => maybe_config_path(Some(PathBuf::from(val))),
+        None => maybe_config_any(&user_config_dir()?, BASE_CONFIG_DEFAULT_NAME),
+    }
 }
 
-fn read_local_config() -> Option<PathBuf> {
-    let local_config_name = match env::var(BINLINK_LOCAL_CONFIG_NAME_EVAR) {
-        Ok(val) => val,
-        Err(_) => String::from(LOCAL_CONFIG_DEFAULT_NAME),
-    };
+/// XDG layout on every platform, `dirs::config_dir` would point into ~/Library on macOS.
+fn user_config_dir() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|h| h.join(".config")));
+    config_home.map(|d| d.join("binlink"))
+}
 
-    let localconfig = maybe_config_in(std::env::current_dir().ok(), local_config_name.as_str());
-    localconfig
+/// Where `--global` edits go: the user config, which may not exist yet.
+pub fn user_config_target() -> Result<PathBuf, Error> {
+    if let Some(path) = env::var_os(BINLINK_BASE_CONFIG_PATH_EVAR) {
+        return Ok(PathBuf::from(path));
+    }
+    let dir = user_config_dir().ok_or_else(|| format_err!("cannot determine user config directory"))?;
+    Ok(maybe_config_any(&dir, BASE_CONFIG_DEFAULT_NAME).unwrap_or_else(|| dir.join(BASE_CONFIG_DEFAULT_NAME)))
 }
 
-fn maybe_config_in(base: Option<PathBuf>, name: &str) -> Option<PathBuf> {
-    maybe_config_path(base.map(|p| p.as_path().join(name)))
+/// Where `--local` edits go: the innermost project config, or a new one in cwd.
+pub fn local_config_target() -> Result<PathBuf, Error> {
+    let paths = get_config_paths()?;
+    if let Some(source) = paths.sources.iter().rev().find(|s| s.kind == LayerKind::Project) {
+        return Ok(source.path.clone());
+    }
+
+    let cwd = env::current_dir().map_err(|e| format_err!("cannot determine current directory: {}", e))?;
+    let name = env::var_os(BINLINK_LOCAL_CONFIG_NAME_EVAR).unwrap_or_else(|| LOCAL_CONFIG_DEFAULT_NAME.into());
+    Ok(cwd.join(name))
+}
+
+fn maybe_config_in<N: AsRef<OsStr>>(base: Option<PathBuf>, name: N) -> Option<PathBuf> {
+    maybe_config_path(base.map(|p| p.as_path().join(name.as_ref())))
+}
+
+/// `name` in `dir` with any supported extension, the first one in `Format::EXTENSIONS` wins.
+fn maybe_config_any<N: AsRef<OsStr>>(dir: &Path, name: N) -> Option<PathBuf> {
+    let mut found = Format::EXTENSIONS.iter()
+        .filter_map(|ext| maybe_config_path(Some(dir.join(name.as_ref()).with_extension(ext))));
+    let first = found.next()?;
+    found.for_each(|ignored| log::warn!("ignoring {} in favour of {}", ignored.display(), first.display()));
+    Some(first)
 }
 
 fn maybe_config_path(path: Option<PathBuf>) -> Option<PathBuf> {
@@ -83,24 +382,74 @@ fn maybe_config_path(path: Option<PathBuf>) -> Option<PathBuf> {
     })
 }
 
-pub fn parse<T>(path: &Path) -> T
+pub fn parse<T>(path: &Path) -> Result<T, Error>
     where
         T: DeserializeOwned,
 {
-    let mut config_toml = String::new();
+    let mut content = String::new();
 
-    let mut file = match File::open(&path) {
-        Ok(file) => file,
-        Err(_) => {
-            panic!("Could not find config file!");
-        }
-    };
+    let mut file = File::open(path)
+        .map_err(|e| format_err!("cannot open config {}: {}", path.display(), e))?;
+
+    file.read_to_string(&mut content)
+        .map_err(|e| format_err!("cannot read config {}: {}", path.display(), e))?;
+
+    match Format::of(path) {
+        Format::Toml => toml::from_str(&content).map_err(|e| {
+            parse_error(path, &content, &e.to_string(), e.line_col().map(|(l, c)| (l + 1, c + 1)))
+        }),
+        Format::Yaml => serde_yaml::from_str(&content).map_err(|e| {
+            parse_error(path, &content, &e.to_string(), e.location().map(|l| (l.line(), l.column())))
+        }),
+        Format::Json => serde_json::from_str(&content).map_err(|e| {
+            let position = if e.line() > 0 { Some((e.line(), e.column())) } else { None };
+            parse_error(path, &content, &e.to_string(), position)
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::temp_dir;
 
-    file.read_to_string(&mut config_toml)
-        .unwrap_or_else(|err| panic!("Error while reading config: [{}]", err));
+    fn stack(name: &str, user: &str, project: (&str, &str)) -> Result<Config, String> {
+        let dir = temp_dir(name);
+        fs::write(dir.join("binlink.toml"), user).unwrap();
+        fs::write(dir.join(project.0), project.1).unwrap();
+        let paths = ConfigPaths {
+            sources: vec![
+                ConfigSource { kind: LayerKind::User, path: dir.join("binlink.toml") },
+                ConfigSource { kind: LayerKind::Project, path: dir.join(project.0) },
+            ],
+        };
+        parse_config(&paths).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn finds_sets_through_the_stack() {
+        let user = "version = 2\n[binsets]\nprotobuf = [\"protoc\"]\n";
+        let project = "version = 2\nbins = [ { set = \"protobuf\", kit = \"protobuf\" }, { set = \"jdk\", kit = \"jdk\" } ]\n";
+        let config = stack("binsets-stack", user, (".binlink.toml", project)).unwrap();
+        assert!(config.names().contains("protoc"));
+        assert!(config.names().contains("javac"));
+    }
+
+    #[test]
+    fn rejects_unknown_sets() {
+        let user = "version = 2\n[binsets]\nprotobuf = [\"protoc\"]\n";
+        let project = "version = 2\n\n[bins]\njava = \"jdk21\"\ntools = { kit = \"protobuf\", set = \"protobf\" }\n";
+        let message = stack("binsets-unknown", user, (".binlink.toml", project)).unwrap_err();
+        assert!(message.contains(".binlink.toml:5:35: unknown bin set `protobf`, expected one of `graal`, `jdk`"), "{}", message);
+        assert!(message.contains("did you mean `protobuf`?"), "{}", message);
+    }
 
-    match toml::from_str(config_toml.as_str()) {
-        Ok(t) => t,
-        Err(e) => panic!(format!("Error while deserializing config: {:#?}", e))
+    #[test]
+    fn locates_sets_in_any_format() {
+        assert_eq!(locate_set("[bins.tools]\nkit = \"jdk\"\nset = \"jdk\"\n", "jdk"), Some((3, 7)));
+        assert_eq!(locate_set("bins:\n  - kit: jdk\n    set: 'jdk'\n", "jdk"), Some((3, 10)));
+        assert_eq!(locate_set("{ \"bins\": [ { \"set\": \"jdk\", \"kit\": \"jdk\" } ] }", "jdk"), Some((1, 22)));
+        assert_eq!(locate_set("binsets = { jdk = [] }\nbins = [ { set = \"jdk17\" }, { set = \"jdk\" } ]\n", "jdk"), Some((2, 37)));
+        assert_eq!(locate_set("reset = \"jdk\"\n", "jdk"), None);
     }
 }
@@ -1,5 +1,7 @@
 use clap::{App, ArgMatches};
-use crate::cfgreader::{BINLINK_LOCAL_CONFIG_NAME_EVAR, BINLINK_BASE_CONFIG_PATH_EVAR};
+use crate::cache::{BINLINK_CACHE_DIR_EVAR, BINLINK_NO_CACHE_EVAR};
+use crate::cond::{BINLINK_ARCH_EVAR, BINLINK_HOSTNAME_EVAR, BINLINK_OS_EVAR};
+use crate::cfgreader::{BINLINK_LOCAL_CONFIG_NAME_EVAR, BINLINK_BASE_CONFIG_PATH_EVAR, BINLINK_SYSTEM_CONFIG_PATH_EVAR, BINLINK_STRICT_EVAR, BINLINK_DISABLE_EVAR, BINLINK_PIN_FILES_EVAR};
 
 pub struct Args {
     help: String
@@ -10,8 +12,27 @@ impl Args {
     pub fn make() -> Args {
         let formatted: String = format!(
             "export {} to override local config name\n\
-             export {} to override global config path\n\
-            ", BINLINK_LOCAL_CONFIG_NAME_EVAR, BINLINK_BASE_CONFIG_PATH_EVAR);
+             export {} to override user config path\n\
+             export {} to override system config path\n\
+             export {} to override resolution cache directory\n\
+             export {} to bypass resolution cache\n\
+             export {} to fail instead of falling back to $PATH on config errors\n\
+             export {} to ignore all config and always use $PATH\n\
+             export {} to honour .sdkmanrc, .nvmrc and similar version files\n\
+             export {}, {} or {} to override facts matched by `when`\n\
+            ",
+            BINLINK_LOCAL_CONFIG_NAME_EVAR,
+            BINLINK_BASE_CONFIG_PATH_EVAR,
+            BINLINK_SYSTEM_CONFIG_PATH_EVAR,
+            BINLINK_CACHE_DIR_EVAR,
+            BINLINK_NO_CACHE_EVAR,
+            BINLINK_STRICT_EVAR,
+            BINLINK_DISABLE_EVAR,
+            BINLINK_PIN_FILES_EVAR,
+            BINLINK_OS_EVAR,
+            BINLINK_ARCH_EVAR,
+            BINLINK_HOSTNAME_EVAR,
+        );
 
         Args { help: formatted }
     }
@@ -24,8 +45,8 @@ impl Args {
         self.defn().print_help().expect("Cannot print help");
     }
 
-    fn defn(self: &Args) -> App {
-        App::new("MyApp")
+    fn defn(self: &Args) -> App<'_> {
+        let app = App::new("MyApp")
             .after_help(self.help.as_str())
             .subcommand(
                 App::new("link")
@@ -34,7 +55,88 @@ impl Args {
             .subcommand(
                 App::new("example")
                     .about("Show config example")
-            ).clone()
+                    .arg(
+                        clap::Arg::new("format")
+                            .long("format")
+                            .takes_value(true)
+                            .possible_values(["toml", "yaml", "json"])
+                            .default_value("toml")
+                    )
+            )
+            .subcommand(
+                App::new("fmt")
+                    .about("Normalize layout of a TOML config, the innermost one by default")
+                    .arg(clap::Arg::new("path"))
+            )
+            .subcommand(
+                App::new("migrate")
+                    .about("Upgrade a TOML config to the current format version, the innermost one by default")
+                    .arg(clap::Arg::new("path"))
+            )
+            .subcommand(
+                App::new("init")
+                    .about("Write a user config for SDKs found on this machine")
+                    .arg(clap::Arg::new("print").long("print").help("Print the config instead of writing it"))
+                    .arg(clap::Arg::new("force").long("force").help("Overwrite an existing user config"))
+            )
+            .subcommand(
+                App::new("use")
+                    .about("Point kits at other paths, e.g. `use jdk=jdk17`")
+                    .arg(clap::Arg::new("mapping").required(true).multiple_values(true))
+                    .arg(clap::Arg::new("local").long("local").help("Edit the innermost project config (default)"))
+                    .arg(clap::Arg::new("global").long("global").conflicts_with("local").help("Edit the user config"))
+            )
+            .subcommand(
+                App::new("add-bin")
+                    .about("Map a binary to a kit")
+                    .arg(clap::Arg::new("name").required(true))
+                    .arg(clap::Arg::new("kit").long("kit").takes_value(true).required(true))
+                    .arg(clap::Arg::new("local").long("local").help("Edit the innermost project config (default)"))
+                    .arg(clap::Arg::new("global").long("global").conflicts_with("local").help("Edit the user config"))
+            )
+            .subcommand(
+                App::new("rm-bin")
+                    .about("Remove mappings of a binary")
+                    .arg(clap::Arg::new("name").required(true))
+                    .arg(clap::Arg::new("local").long("local").help("Edit the innermost project config (default)"))
+                    .arg(clap::Arg::new("global").long("global").conflicts_with("local").help("Edit the user config"))
+            )
+            .subcommand(
+                App::new("import")
+                    .about("Write kits pinned by .sdkmanrc, .nvmrc, .tool-versions and similar files in cwd into the project config")
+            )
+            .subcommand(
+                App::new("export")
+                    .about("Write versions of kits in use for other tools")
+                    .arg(clap::Arg::new("tool-versions").long("tool-versions").required(true)
+                        .help("Update .tool-versions in cwd for asdf and mise"))
+            )
+            .subcommand(
+                App::new("schema")
+                    .about("Show JSON Schema of the config format, for editor completion and validation")
+            )
+            .subcommand(
+                App::new("which")
+                    .about("Show config stack and where a binary resolves to")
+                    .arg(clap::Arg::new("name"))
+            )
+            .subcommand(
+                App::new("cache")
+                    .about("Manage resolution cache")
+                    .subcommand(
+                        App::new("clear")
+                            .about("Remove all cached resolutions")
+                    )
+            );
+
+        #[cfg(feature = "download")]
+        let app = app.subcommand(
+            App::new("download")
+                .about("Download a file into the current directory")
+                .arg(clap::Arg::new("url").required(true))
+        );
+
+        app.clone()
     }
 
 }
@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::CStr;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+pub const BINLINK_OS_EVAR: &str = "BINLINK_OS";
+pub const BINLINK_ARCH_EVAR: &str = "BINLINK_ARCH";
+pub const BINLINK_HOSTNAME_EVAR: &str = "BINLINK_HOSTNAME";
+
+/// What `when` conditions are matched against.
+///
+/// Each fact can be overridden through the environment, e.g. to check a macOS config on Linux.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Hash)]
+pub struct Facts {
+    pub os: String,
+    pub arch: String,
+    pub hostname: String,
+}
+
+impl Facts {
+    pub fn detect() -> Facts {
+        Facts {
+            os: env::var(BINLINK_OS_EVAR).unwrap_or_else(|_| String::from(env::consts::OS)),
+            arch: env::var(BINLINK_ARCH_EVAR).unwrap_or_else(|_| String::from(env::consts::ARCH)),
+            hostname: env::var(BINLINK_HOSTNAME_EVAR).unwrap_or_else(|_| hostname()),
+        }
+    }
+}
+
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    let out = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if out != 0 {
+        return String::new();
+    }
+    // not NUL-terminated when truncated
+    buf[buf.len() - 1] = 0;
+    let name = unsafe { CStr::from_ptr(buf.as_ptr() as *const libc::c_char) };
+    name.to_string_lossy().into_owned()
+}
+
+/// Limits a config entry to some machines, all given fields must match.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Condition {
+    /// as in `std::env::consts::OS`: `linux`, `macos`, ...
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os: Option<String>,
+    /// as in `std::env::consts::ARCH`: `x86_64`, `aarch64`, ...
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+    /// glob, e.g. `ci-*`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// variable name to a glob its value must match, `*` only requires it to be set
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+}
+
+impl Condition {
+    pub fn matches(&self, facts: &Facts) -> bool {
+        let os = self.os.iter().all(|os| *os == facts.os);
+        let arch = self.arch.iter().all(|arch| *arch == facts.arch);
+        let hostname = self.hostname.iter().all(|h| glob_matches(h, &facts.hostname));
+        let env = self.env.iter().all(|(name, pattern)| {
+            match env::var(name) {
+                Ok(value) => glob_matches(pattern, &value),
+                Err(_) => false,
+            }
+        });
+
+        os && arch && hostname && env
+    }
+}
+
+fn glob_matches(pattern: &str, value: &str) -> bool {
+    match glob::Pattern::new(pattern) {
+        Ok(p) => p.matches(value),
+        Err(e) => {
+            log::warn!("invalid pattern `{}` never matches: {}", pattern, e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn facts() -> Facts {
+        Facts { os: String::from("macos"), arch: String::from("aarch64"), hostname: String::from("ci-runner-7") }
+    }
+
+    fn when(toml: &str) -> Condition {
+        toml::from_str(toml).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[test]
+    fn matches_os_and_arch() {
+        assert!(when("").matches(&facts()));
+        assert!(when("os = \"macos\"").matches(&facts()));
+        assert!(when("os = \"macos\"\narch = \"aarch64\"").matches(&facts()));
+        assert!(!when("os = \"linux\"").matches(&facts()));
+        assert!(!when("os = \"macos\"\narch = \"x86_64\"").matches(&facts()));
+    }
+
+    #[test]
+    fn matches_hostname_globs() {
+        assert!(when("hostname = \"ci-*\"").matches(&facts()));
+        assert!(when("hostname = \"ci-runner-?\"").matches(&facts()));
+        assert!(!when("hostname = \"ci-runner\"").matches(&facts()));
+        assert!(!when("hostname = \"laptop*\"").matches(&facts()));
+        // invalid patterns never match
+        assert!(!when("hostname = \"ci-[\"").matches(&facts()));
+    }
+
+    #[test]
+    fn matches_env_globs() {
+        env::set_var("BINLINK_TEST_COND_SET", "prod-eu");
+        env::set_var("BINLINK_TEST_COND_EMPTY", "");
+        env::remove_var("BINLINK_TEST_COND_UNSET");
+
+        assert!(when("env = { BINLINK_TEST_COND_SET = \"*\" }").matches(&facts()));
+        assert!(when("env = { BINLINK_TEST_COND_SET = \"prod-*\" }").matches(&facts()));
+        assert!(!when("env = { BINLINK_TEST_COND_SET = \"dev-*\" }").matches(&facts()));
+        assert!(when("env = { BINLINK_TEST_COND_EMPTY = \"*\" }").matches(&facts()));
+        assert!(!when("env = { BINLINK_TEST_COND_UNSET = \"*\" }").matches(&facts()));
+        assert!(!when("os = \"linux\"\nenv = { BINLINK_TEST_COND_SET = \"*\" }").matches(&facts()));
+    }
+}
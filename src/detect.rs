@@ -0,0 +1,367 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::cfg::CONFIG_VERSION;
+
+/// An SDK found on this machine, possibly in several versions.
+#[derive(Debug)]
+pub struct Kit {
+    pub name: String,
+    /// where it was found, for the generated comments
+    pub source: String,
+    /// installation dirs, oldest first
+    pub installs: Vec<PathBuf>,
+    /// executables dir inside an installation
+    pub bin: String,
+    pub target: Target,
+    /// executables of the newest install
+    pub bins: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum Target {
+    Dir { path: String },
+    HighestPrefix { base: String, prefix: String, path: String },
+}
+
+/// Scans well-known install locations, user-level managers first so their binaries win.
+pub fn scan() -> Vec<Kit> {
+    let mut kits = Vec::new();
+
+    if let Some(home) = dirs::home_dir() {
+        let sdkman = home.join(".sdkman/candidates");
+        for candidate in subdirs(&sdkman) {
+            let name = file_name(&candidate);
+            kits.extend(versions(&name, &candidate, "bin", "sdkman"));
+        }
+        kits.extend(versions("node", &home.join(".nvm/versions/node"), "bin", "nvm"));
+        kits.extend(versions("python", &home.join(".pyenv/versions"), "bin", "pyenv"));
+        // macOS jabba installs are bundles
+        let jabba = home.join(".jabba/jdk");
+        let jabba_bin = if cfg!(target_os = "macos") { "Contents/Home/bin" } else { "bin" };
+        kits.extend(versions("jabba", &jabba, jabba_bin, "jabba"));
+    }
+
+    let (graal, jdk): (Vec<PathBuf>, Vec<PathBuf>) = subdirs(Path::new("/usr/lib/jvm")).into_iter()
+        .filter(|d| is_executable(&d.join("bin/java")))
+        .partition(|d| file_name(d).contains("graal"));
+    kits.extend(family("jdk", Path::new("/usr/lib/jvm"), jdk, "bin", "system JDKs"));
+    kits.extend(family("graal", Path::new("/usr/lib/jvm"), graal, "bin", "system JDKs"));
+
+    for dir in subdirs(Path::new("/opt")) {
+        if !executables(&dir.join("bin")).is_empty() {
+            let name = file_name(&dir);
+            kits.extend(family(&name, Path::new("/opt"), vec![dir], "bin", "/opt"));
+        }
+    }
+
+    dedupe(&mut kits);
+    kits
+}
+
+/// Gives kits sharing a name, e.g. pyenv's `python` and `/opt/python`, distinct ones:
+/// the first keeps it, later ones get their source appended, `python-opt`.
+fn dedupe(kits: &mut [Kit]) {
+    let mut taken = HashSet::new();
+    for kit in kits.iter_mut() {
+        if taken.insert(kit.name.clone()) {
+            continue;
+        }
+        let source: String = kit.source.split(|c: char| !c.is_ascii_alphanumeric())
+            .find(|w| !w.is_empty())
+            .unwrap_or("other")
+            .to_ascii_lowercase();
+        let base = format!("{}-{}", kit.name, source);
+        let name = (1..)
+            .map(|n| if n == 1 { base.clone() } else { format!("{}-{}", base, n) })
+            .find(|name| !taken.contains(name))
+            .expect("unbounded");
+        taken.insert(name.clone());
+        kit.name = name;
+    }
+}
+
+/// Every subdir of `base` is a version, e.g. `~/.nvm/versions/node/v18.12.1`.
+fn versions(name: &str, base: &Path, bin: &str, source: &str) -> Option<Kit> {
+    // the manager's own default beats guessing
+    let current = base.join("current");
+    if current.join(bin).is_dir() {
+        let installs: Vec<PathBuf> = sorted(subdirs(base).into_iter().filter(|d| *d != current).collect());
+        return Some(Kit {
+            name: String::from(name),
+            source: String::from(source),
+            bins: executables(&current.join(bin)),
+            target: Target::Dir { path: home_relative(&current.join(bin)) },
+            installs,
+            bin: String::from(bin),
+        });
+    }
+
+    let installs = subdirs(base).into_iter().filter(|d| d.join(bin).is_dir()).collect();
+    family(name, base, installs, bin, source)
+}
+
+/// `installs` are all in `base`, the newest one is picked.
+fn family(name: &str, base: &Path, installs: Vec<PathBuf>, bin: &str, source: &str) -> Option<Kit> {
+    let installs = sorted(installs);
+    let newest = installs.last()?.clone();
+
+    let names: Vec<String> = installs.iter().map(|d| file_name(d)).collect();
+    let prefix = common_prefix(&names);
+    // `HighestPrefix` compares names as strings, only use it when that agrees with version order
+    let lexical_max = names.iter().max().cloned();
+    let target = if installs.len() > 1 && !prefix.is_empty() && lexical_max.as_ref() == names.last() {
+        Target::HighestPrefix { base: home_relative(base), prefix, path: String::from(bin) }
+    } else {
+        Target::Dir { path: home_relative(&newest.join(bin)) }
+    };
+
+    Some(Kit {
+        name: String::from(name),
+        source: String::from(source),
+        bins: executables(&newest.join(bin)),
+        target,
+        installs,
+        bin: String::from(bin),
+    })
+}
+
+/// A commented global config mapping every found binary to the first kit providing it.
+pub fn render(kits: &[Kit]) -> String {
+    let mut out = String::new();
+    out.push_str("# Generated by `binlink init` from SDKs installed on this machine.\n");
+    out.push_str("# Kits point at the newest version found, see `binlink use` to pin another one.\n");
+    out.push_str(&format!("version = {}\n", CONFIG_VERSION));
+
+    // every install gets an id, for `binlink use` and version files like `.nvmrc`
+    let installs: Vec<String> = kits.iter()
+        .flat_map(|kit| kit.installs.iter().map(move |i| (kit, i)))
+        .map(|(kit, install)| {
+            let dir = file_name(install);
+            let id = if dir.starts_with(&kit.name) { dir } else { format!("{}-{}", kit.name, dir) };
+            format!("  {{ id = {}, target = {{ type = \"Dir\", path = {} }} }},\n", string(&id), string(&home_relative(&install.join(&kit.bin))))
+        })
+        .collect();
+    if !installs.is_empty() {
+        out.push_str(&format!("paths = [\n{}]\n", installs.concat()));
+    }
+
+    let mut owner: BTreeMap<&str, &str> = BTreeMap::new();
+    let mut shadowed: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for kit in kits {
+        for bin in &kit.bins {
+            match owner.get(bin.as_str()) {
+                Some(_) => shadowed.entry(bin.as_str()).or_default().push(&kit.name),
+                None => { owner.insert(bin, &kit.name); }
+            }
+        }
+    }
+
+    if kits.is_empty() {
+        out.push_str("\n# No SDKs found in /usr/lib/jvm, /opt, ~/.sdkman, ~/.nvm, ~/.pyenv or ~/.jabba.\n");
+        return out;
+    }
+
+    for kit in kits {
+        out.push_str(&format!("\n[kits.{}]\n", key(&kit.name)));
+        out.push_str(&format!("# {}:\n", kit.source));
+        kit.installs.iter().for_each(|i| out.push_str(&format!("#   {}\n", home_relative(i))));
+        let target = match &kit.target {
+            Target::Dir { path } => format!("{{ type = \"Dir\", path = {} }}", string(path)),
+            Target::HighestPrefix { base, prefix, path } => format!(
+                "{{ type = \"HighestPrefix\", base = {}, prefix = {}, path = {} }}",
+                string(base), string(prefix), string(path),
+            ),
+        };
+        out.push_str(&format!("target = {}\n", target));
+    }
+
+    out.push_str("\n[bins]\n");
+    for kit in kits {
+        let bins: Vec<&String> = kit.bins.iter().filter(|b| owner.get(b.as_str()) == Some(&kit.name.as_str())).collect();
+        if bins.is_empty() {
+            continue;
+        }
+        out.push_str(&format!("# {}\n", kit.name));
+        for bin in bins {
+            let line = format!("{} = {}", key(bin), string(&kit.name));
+            match shadowed.get(bin.as_str()) {
+                Some(others) => out.push_str(&format!("{}  # also in {}\n", line, others.join(", "))),
+                None => out.push_str(&format!("{}\n", line)),
+            }
+        }
+    }
+
+    out
+}
+
+/// Compares embedded numbers by value, so `java-8` sorts before `java-11`.
+pub fn version_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let na = take_number(&mut a);
+                let nb = take_number(&mut b);
+                // compare as numbers without overflowing on long digit runs
+                let (ta, tb) = (na.trim_start_matches('0'), nb.trim_start_matches('0'));
+                let ord = ta.len().cmp(&tb.len()).then_with(|| ta.cmp(tb));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+fn take_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut out = String::new();
+    while let Some(c) = chars.peek().copied().filter(char::is_ascii_digit) {
+        out.push(c);
+        chars.next();
+    }
+    out
+}
+
+fn sorted(mut dirs: Vec<PathBuf>) -> Vec<PathBuf> {
+    dirs.sort_by(|a, b| version_cmp(&file_name(a), &file_name(b)));
+    dirs
+}
+
+/// Real directories only, symlinks like `default-java` would duplicate installs.
+fn subdirs(dir: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut out: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().map(|t| t.is_dir()).unwrap_or(false))
+        .map(|e| e.path())
+        .collect();
+    out.sort();
+    out
+}
+
+fn executables(dir: &Path) -> Vec<String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut out: Vec<String> = entries
+        .filter_map(Result::ok)
+        .filter(|e| is_executable(&e.path()))
+        .filter_map(|e| e.file_name().to_str().map(String::from))
+        .collect();
+    out.sort();
+    out
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0).unwrap_or(false)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+fn common_prefix(names: &[String]) -> String {
+    let first = match names.first() {
+        Some(first) => first,
+        None => return String::new(),
+    };
+    let mut len = first.len();
+    for name in names {
+        len = first.char_indices()
+            .zip(name.chars())
+            .take_while(|((i, a), b)| *i < len && a == b)
+            .map(|((i, a), _)| i + a.len_utf8())
+            .last()
+            .unwrap_or(0);
+    }
+    // don't cut into a version number, `jdk-1` would also match `jdk-17`
+    let cut = first[..len].trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    String::from(cut)
+}
+
+/// `~/...` keeps the generated config usable after copying it to another machine.
+fn home_relative(path: &Path) -> String {
+    match dirs::home_dir().and_then(|h| path.strip_prefix(h).ok().map(Path::to_path_buf)) {
+        Some(rest) => format!("~/{}", rest.display()),
+        None => path.display().to_string(),
+    }
+}
+
+fn string(s: &str) -> String {
+    toml::Value::String(String::from(s)).to_string()
+}
+
+fn key(s: &str) -> String {
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        String::from(s)
+    } else {
+        string(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::FileConfig;
+
+    fn kit(name: &str, source: &str, install: &str) -> Kit {
+        Kit {
+            name: String::from(name),
+            source: String::from(source),
+            installs: vec![PathBuf::from(install)],
+            bin: String::from("bin"),
+            target: Target::Dir { path: format!("{}/bin", install) },
+            bins: vec![String::from(name)],
+        }
+    }
+
+    #[test]
+    fn renders_colliding_kits_as_distinct_tables() {
+        let mut kits = vec![
+            kit("python", "pyenv", "/home/me/.pyenv/versions/3.11.1"),
+            kit("node", "nvm", "/home/me/.nvm/versions/node/v18.12.1"),
+            kit("python", "/opt", "/opt/python"),
+            kit("node", "/opt", "/opt/node"),
+            kit("node", "/opt", "/opt/node2"),
+        ];
+        dedupe(&mut kits);
+
+        let names: Vec<&str> = kits.iter().map(|k| k.name.as_str()).collect();
+        assert_eq!(names, vec!["python", "node", "python-opt", "node-opt", "node-opt-2"]);
+
+        let rendered = render(&kits);
+        let config: FileConfig = toml::from_str(&rendered).unwrap_or_else(|e| panic!("{}\n{}", e, rendered));
+        let kit_names: Vec<&str> = config.kits.iter().map(|k| k.name.as_str()).collect();
+        assert_eq!(kit_names, vec!["node", "node-opt", "node-opt-2", "python", "python-opt"]);
+    }
+
+    #[test]
+    fn compares_numbers_by_value() {
+        assert_eq!(version_cmp("17.0.12", "17.0.5"), Ordering::Greater);
+        assert_eq!(version_cmp("java-8", "java-11"), Ordering::Less);
+        assert_eq!(version_cmp("17.0.5-tem", "17.0.5-tem"), Ordering::Equal);
+        assert_eq!(version_cmp("17.0.5", "17.0.5-tem"), Ordering::Less);
+        assert_eq!(version_cmp("v007", "v7"), Ordering::Equal);
+        assert_eq!(version_cmp("99999999999999999999999", "100000000000000000000000"), Ordering::Less);
+        assert_eq!(version_cmp("17.0.5-open", "17.0.5-tem"), Ordering::Less);
+    }
+
+}
@@ -0,0 +1,132 @@
+use std::path::Path;
+
+use failure::{format_err, Error};
+
+/// Config parse error with the offending line, a caret under `position` and a "did you mean" hint.
+///
+/// `position` is 1-based line and column.
+pub fn parse_error(path: &Path, content: &str, message: &str, position: Option<(usize, usize)>) -> Error {
+    // the location goes into the header and the snippet instead
+    let message = match message.rfind(" at line ") {
+        Some(idx) if position.is_some() => &message[..idx],
+        _ => message,
+    };
+
+    let mut out = match position {
+        Some((line, column)) => format!("cannot parse config {}:{}:{}: {}", path.display(), line, column, message),
+        None => format!("cannot parse config {}: {}", path.display(), message),
+    };
+
+    let text = position.and_then(|(line, _)| content.lines().nth(line.saturating_sub(1)));
+
+    if let (Some((line, column)), Some(text)) = (position, text) {
+        let gutter = " ".repeat(line.to_string().len());
+        let pad: String = text.chars().take(column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        out.push_str(&format!("\n{} |\n{} | {}\n{} | {}^", gutter, line, text, gutter, pad));
+    }
+
+    if let Some(hint) = unknown_name_hint(message) {
+        out.push_str(&format!("\n  = did you mean `{}`?", hint));
+    } else if let Some((typo, field)) = missing_field_hint(message, text, position) {
+        out.push_str(&format!("\n  = did you mean `{}` instead of `{}`?", field, typo));
+    }
+
+    format_err!("{}", out)
+}
+
+/// Closest expected name for serde's "unknown field/variant `x`, expected ..." messages and unknown bin sets.
+fn unknown_name_hint(message: &str) -> Option<&str> {
+    let rest = after(message, "unknown field `")
+        .or_else(|| after(message, "unknown variant `"))
+        .or_else(|| after(message, "unknown bin set `"))?;
+    let end = rest.find('`')?;
+    let (unknown, expected) = (&rest[..end], &rest[end + 1..]);
+
+    // expected names are the remaining backticked words
+    closest(unknown, expected.split('`').skip(1).step_by(2))
+}
+
+/// A misspelled key on the reported line for "missing field `x`", e.g. `typ` for the `type` tag.
+fn missing_field_hint<'a>(message: &str, text: Option<&'a str>, position: Option<(usize, usize)>) -> Option<(&'a str, String)> {
+    let rest = after(message, "missing field `")?;
+    let field = &rest[..rest.find('`')?];
+
+    let text = text?;
+    let start = text.char_indices().nth(position?.1.saturating_sub(1)).map(|(i, _)| i).unwrap_or(0);
+    let keys = text[start..]
+        .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+        .filter(|k| !k.is_empty() && *k != field);
+
+    keys.map(|k| (distance(k, field), k))
+        .filter(|(d, _)| *d > 0 && *d <= (field.chars().count() / 3).max(1))
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, k)| (k, String::from(field)))
+}
+
+fn after<'a>(message: &'a str, marker: &str) -> Option<&'a str> {
+    message.find(marker).map(|idx| &message[idx + marker.len()..])
+}
+
+fn closest<'a>(unknown: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    candidates
+        .map(|candidate| (distance(unknown, candidate), candidate))
+        .filter(|(d, candidate)| *d <= (candidate.chars().count() / 3).max(1))
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance where swapping two adjacent characters is one edit (`Dri` for `Dir`),
+/// case differences count as half an edit rounded down.
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    let mut before: Vec<usize> = Vec::new();
+    let mut prev: Vec<usize> = (0..=b.len()).map(|j| j * 2).collect();
+    for i in 1..=a.len() {
+        let mut cur = vec![i * 2; b.len() + 1];
+        for j in 1..=b.len() {
+            let subst = if a[i - 1] == b[j - 1] {
+                0
+            } else if a[i - 1].eq_ignore_ascii_case(&b[j - 1]) {
+                1
+            } else {
+                2
+            };
+            cur[j] = (prev[j - 1] + subst).min(prev[j] + 2).min(cur[j - 1] + 2);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                cur[j] = cur[j].min(before[j - 2] + 2);
+            }
+        }
+        before = std::mem::replace(&mut prev, cur);
+    }
+
+    prev[b.len()] / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_edits() {
+        assert_eq!(distance("kit", "kit"), 0);
+        assert_eq!(distance("Kit", "kit"), 0);
+        assert_eq!(distance("kt", "kit"), 1);
+        assert_eq!(distance("kti", "kit"), 1);
+        assert_eq!(distance("Dri", "Dir"), 1);
+        assert_eq!(distance("tikp", "kit"), 3);
+        assert_eq!(distance("", "kit"), 3);
+    }
+
+    #[test]
+    fn hints_transposed_names() {
+        let variant = "unknown variant `Dri`, expected one of `Dir`, `HighestPrefix`, `Sdkman`";
+        assert_eq!(unknown_name_hint(variant), Some("Dir"));
+        let field = "bins[0]: unknown field `kti`, expected one of `name`, `set`, `kit`, `target` at line 3 column 5";
+        assert_eq!(unknown_name_hint(field), Some("kit"));
+        assert_eq!(unknown_name_hint("unknown field `xyz`, expected `kit`"), None);
+    }
+}
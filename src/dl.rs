@@ -17,14 +17,13 @@ struct DownloadProgress<R> {
 
 impl<R: Read> Read for DownloadProgress<R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.inner.read(buf).map(|n| {
+        self.inner.read(buf).inspect(|&n| {
             self.progress_bar.inc(n as u64);
-            n
         })
     }
 }
 
-fn download(url: &str) -> Result<(), ExitFailure> {
+pub fn download(url: &str) -> Result<(), ExitFailure> {
     let url = Url::parse(url)?;
     let client = req::Client::new();
 
@@ -33,7 +32,7 @@ fn download(url: &str) -> Result<(), ExitFailure> {
     let file = Path::new(
         url
             .path_segments()
-            .and_then(|segments| segments.last())
+            .and_then(|mut segments| segments.next_back())
             .unwrap_or("tmp.bin"),
     );
 
@@ -42,7 +41,7 @@ fn download(url: &str) -> Result<(), ExitFailure> {
     let mut dest = fs::OpenOptions::new()
         .create(true)
         .append(true)
-        .open(&file)?;
+        .open(file)?;
 
 
     pb.set_style(ProgressStyle::default_bar()
@@ -0,0 +1,787 @@
+use std::fs;
+use std::path::Path;
+
+use failure::{format_err, Error};
+use toml_edit::{decorated, value, Array, ArrayOfTables, Document, InlineTable, Item, Key, Table, Value};
+
+use crate::cfg::CONFIG_VERSION;
+use crate::cfgreader::Format;
+
+/// Upgrades a file from version `n + 1` to `n + 2`, notes are shown to the user.
+type Migration = fn(&mut Document, &mut Vec<String>);
+
+const MIGRATIONS: [Migration; 1] = [v1_to_v2];
+
+/// TOML config as an editable document, comments and key order survive a round trip.
+pub fn load(path: &Path) -> Result<Document, Error> {
+    if Format::of(path) != Format::Toml {
+        return Err(format_err!("cannot edit {}: only TOML configs can be rewritten", path.display()));
+    }
+
+    let content = fs::read_to_string(path)
+        .map_err(|e| format_err!("cannot read config {}: {}", path.display(), e))?;
+    content.parse::<Document>()
+        .map_err(|e| format_err!("cannot parse config {}: {}", path.display(), e))
+}
+
+/// Like `load`, but a missing file is a new empty config.
+pub fn load_or_new(path: &Path) -> Result<Document, Error> {
+    if path.exists() {
+        return load(path);
+    }
+    let mut doc = Document::new();
+    doc["version"] = value(CONFIG_VERSION);
+    Ok(doc)
+}
+
+pub fn save(path: &Path, doc: &Document) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format_err!("cannot create {}: {}", dir.display(), e))?;
+    }
+    fs::write(path, doc.to_string())
+        .map_err(|e| format_err!("cannot write config {}: {}", path.display(), e))
+}
+
+/// Inline tables for everything below an entry, `{ a = 1, b = 2 }` spacing and one array entry per line.
+pub fn normalize(doc: &mut Document) {
+    for (_, item) in doc.as_table_mut().iter_mut() {
+        match item {
+            Item::ArrayOfTables(entries) => {
+                for idx in 0..entries.len() {
+                    inline_subtables(entries.get_mut(idx).expect("expected array element"));
+                }
+            }
+            // `[bins]` with entries as values, `[kits.jdk]` as a sub-table
+            Item::Table(section) => {
+                for (_, entry) in section.iter_mut() {
+                    match entry {
+                        Item::Table(entry) => inline_subtables(entry),
+                        Item::Value(v) => normalize_value(v),
+                        _ => (),
+                    }
+                }
+            }
+            Item::Value(v) => normalize_value(v),
+            Item::None => (),
+        }
+    }
+}
+
+fn inline_subtables(entry: &mut Table) {
+    let rebuilt = if entry.iter().any(|(_, item)| item.is_table()) { rebuilt(entry) } else { None };
+    if let Some(table) = rebuilt {
+        *entry = table;
+        return;
+    }
+
+    for (_, item) in entry.iter_mut() {
+        let inline = match item {
+            Item::Table(sub) => inline_table(sub),
+            Item::Value(v) => {
+                normalize_value(v);
+                continue;
+            }
+            _ => continue,
+        };
+        *item = value(Value::InlineTable(inline));
+    }
+}
+
+/// `entry` with its sub-tables inlined and their comments above the new keys.
+///
+/// `Table` can't decorate keys it didn't parse, so the entry goes through text; `None` for what can't.
+fn rebuilt(entry: &Table) -> Option<Table> {
+    const KEY: &str = "binlink-rebuilt";
+
+    let mut body = String::new();
+    for (key, item) in entry.iter() {
+        let raw = key.parse::<Key>().ok()?;
+        match item {
+            Item::Value(v) => {
+                let decor = entry.decor(key)?;
+                let mut v = v.clone();
+                normalize_value(&mut v);
+                body.push_str(&format!("{}{}{}={}\n", decor.prefix(), raw.raw(), decor.suffix(), v));
+            }
+            Item::Table(sub) => {
+                let comments: String = comment_lines(sub).iter().map(|c| format!("{}\n", c)).collect();
+                let inline = Value::InlineTable(inline_table(sub));
+                body.push_str(&format!("{}{} = {}\n", comments, raw.raw(), inline.to_string().trim()));
+            }
+            _ => return None,
+        }
+    }
+
+    let (prefix, suffix) = header_decor(entry).unwrap_or_else(|| (String::from("\n"), String::new()));
+    let doc = format!("{}[{}]{}\n{}", prefix, KEY, suffix, body).parse::<Document>().ok()?;
+    doc.as_table().get(KEY)?.as_table().cloned()
+}
+
+/// What precedes a table's header and follows it on the line, `None` for implicit tables without one.
+fn header_decor(table: &Table) -> Option<(String, String)> {
+    let text = wrapped(table);
+    let start = text.find("[binlink-wrapped]")?;
+    let end = start + "[binlink-wrapped]".len();
+    let line_end = text[end..].find('\n').map(|i| end + i).unwrap_or_else(|| text.len());
+    Some((String::from(&text[..start]), String::from(&text[end..line_end])))
+}
+
+/// Whole-line comments of `table` and its sub-tables, header ones included, which inlining would drop.
+fn comment_lines(table: &Table) -> Vec<String> {
+    wrapped(table).lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            let comment = match line.strip_prefix('[') {
+                // `[a.b] # note`
+                Some(_) => &line[line.find(']')? + 1..],
+                None => line,
+            };
+            Some(comment.trim()).filter(|c| c.starts_with('#')).map(String::from)
+        })
+        .collect()
+}
+
+/// `table` rendered as `[binlink-wrapped]`, the only way to see the decor of its header.
+fn wrapped(table: &Table) -> String {
+    let mut wrapper = Table::new();
+    wrapper["binlink-wrapped"] = Item::Table(table.clone());
+    wrapper.to_string()
+}
+
+fn inline_table(table: &Table) -> InlineTable {
+    let mut inline = InlineTable::default();
+    table.iter().for_each(|(key, item)| {
+        let mut v = match item {
+            Item::Value(v) => v.clone(),
+            Item::Table(sub) => Value::InlineTable(inline_table(sub)),
+            _ => return,
+        };
+        normalize_value(&mut v);
+        inline.get_or_insert(key, v);
+    });
+    inline.fmt();
+    inline
+}
+
+fn normalize_value(v: &mut Value) {
+    match v {
+        Value::InlineTable(t) => {
+            let keys: Vec<String> = t.iter().map(|(k, _)| String::from(k)).collect();
+            for key in keys {
+                if let Some(nested) = t.get_mut(&key) {
+                    normalize_value(nested);
+                }
+            }
+            t.fmt();
+        }
+        Value::Array(a) => normalize_array(a),
+        _ => (),
+    }
+}
+
+fn normalize_array(a: &mut Array) {
+    let tables = a.iter().any(|v| v.is_inline_table());
+    let commented = a.to_string().contains('#');
+
+    if !tables {
+        // `fmt` would drop comments between values
+        if !commented {
+            a.fmt();
+        }
+        return;
+    }
+
+    let last = a.len().saturating_sub(1);
+    for idx in 0..a.len() {
+        let mut v = a.get(idx).expect("expected array element").clone();
+        normalize_value(&mut v);
+        // keep comments above an entry
+        let comments: String = v.decor().prefix().lines()
+            .map(str::trim)
+            .filter(|l| l.starts_with('#'))
+            .map(|l| format!("\n  {}", l))
+            .collect();
+        a.replace_formatted(idx, decorated(v, &format!("{}\n  ", comments), ""))
+            .expect("same value type");
+        if idx == last {
+            // close on its own line unless the array already ends with a newline
+            let rendered = a.to_string();
+            let before = rendered[..rendered.rfind(']').unwrap_or(0)].trim_end_matches([' ', '\t']);
+            if !before.ends_with('\n') {
+                let v = a.get(idx).expect("expected array element").clone();
+                let prefix = String::from(v.decor().prefix());
+                a.replace_formatted(idx, decorated(v, &prefix, "\n")).expect("same value type");
+            }
+        }
+    }
+}
+
+/// Rewrites `doc` to `CONFIG_VERSION`, returns notes about changes which need a human look.
+pub fn migrate(doc: &mut Document) -> Result<(i64, Vec<String>), Error> {
+    let from = match doc.as_table().get("version") {
+        None => 1,
+        Some(v) => v.as_integer().ok_or_else(|| format_err!("`version` must be an integer"))?,
+    };
+    if !(1..=CONFIG_VERSION).contains(&from) {
+        return Err(format_err!("unsupported config version {}, this binlink reads up to {}", from, CONFIG_VERSION));
+    }
+
+    let mut notes = Vec::new();
+    for step in &MIGRATIONS[(from - 1) as usize..] {
+        step(doc, &mut notes);
+    }
+    doc["version"] = value(CONFIG_VERSION);
+
+    Ok((from, notes))
+}
+
+/// Version 1 used kit paths as written, version 2 expands variables and resolves relative paths against the file.
+/// A leading `/` of `HighestPrefix.path` never left the picked entry, it is dropped to say so.
+fn v1_to_v2(doc: &mut Document, notes: &mut Vec<String>) {
+    match doc.as_table_mut().entry("paths") {
+        Item::ArrayOfTables(paths) => {
+            for idx in 0..paths.len() {
+                let entry = paths.get_mut(idx).expect("expected array element");
+                let id = entry["id"].as_str().map(String::from).unwrap_or_default();
+                if let Some(target) = Fields::target(entry) {
+                    v1_target(&format!("paths[{}].target", id), target, notes);
+                }
+            }
+        }
+        Item::Value(Value::Array(paths)) => {
+            for idx in 0..paths.len() {
+                let mut entry = paths.get(idx).expect("expected array element").clone();
+                if let Value::InlineTable(t) = &mut entry {
+                    let id = t.get("id").and_then(Value::as_str).map(String::from).unwrap_or_default();
+                    if let Some(target) = Fields::target(t) {
+                        v1_target(&format!("paths[{}].target", id), target, notes);
+                    }
+                }
+                paths.replace_formatted(idx, entry).expect("same value type");
+            }
+        }
+        _ => (),
+    }
+
+    // `[kits.jdk]` with a `target` or a `path`, `[kits] jdk = "path"`
+    if let Item::Table(kits) = doc.as_table_mut().entry("kits") {
+        for (name, item) in kits.iter_mut() {
+            let kit: &mut dyn Fields = match item {
+                Item::Table(t) => t,
+                Item::Value(Value::InlineTable(t)) => t,
+                Item::Value(Value::String(path)) => {
+                    v1_path(&format!("kits.{}", name), path.value(), true, notes);
+                    continue;
+                }
+                _ => continue,
+            };
+            if let Some(path) = kit.get("path") {
+                v1_path(&format!("kits.{}.path", name), &path, true, notes);
+            }
+            if let Some(target) = kit.target() {
+                v1_target(&format!("kits.{}.target", name), target, notes);
+            }
+        }
+    }
+}
+
+fn v1_target(label: &str, target: &mut dyn Fields, notes: &mut Vec<String>) {
+    let kind = target.get("type");
+    if kind.as_deref() == Some("HighestPrefix") {
+        if let Some(path) = target.get("path").filter(|p| p.starts_with('/')) {
+            let relative = path.trim_start_matches('/');
+            target.set("path", relative);
+            notes.push(format!("{}.path: `{}` became `{}`, it is relative to the picked entry", label, path, relative));
+        }
+    }
+    for field in &["path", "base"] {
+        if let Some(v) = target.get(field) {
+            // `HighestPrefix.path` is inside the picked entry, never against the file
+            let anchored = *field == "base" || kind.as_deref() == Some("Dir");
+            v1_path(&format!("{}.{}", label, field), &v, anchored, notes);
+        }
+    }
+}
+
+/// Notes for a path version 2 reads differently.
+fn v1_path(label: &str, v: &str, anchored: bool, notes: &mut Vec<String>) {
+    if v.contains('{') || v.contains('$') {
+        notes.push(format!("{}: `{}` is now subject to variable expansion", label, v));
+    } else if v == "~" || v.starts_with("~/") {
+        notes.push(format!("{}: `{}` now starts in the home directory", label, v));
+    } else if anchored && !v.starts_with('/') {
+        notes.push(format!("{}: `{}` is now relative to the config file in project configs", label, v));
+    }
+}
+
+/// String fields and the `target` of a table, whether written as a sub-table or inline.
+trait Fields {
+    fn get(&self, key: &str) -> Option<String>;
+    /// replaces the value, keeping its surrounding whitespace and comments
+    fn set(&mut self, key: &str, v: &str);
+    fn target(&mut self) -> Option<&mut dyn Fields>;
+}
+
+impl Fields for Table {
+    fn get(&self, key: &str) -> Option<String> {
+        Table::get(self, key).and_then(Item::as_str).map(String::from)
+    }
+
+    fn set(&mut self, key: &str, v: &str) {
+        if let Some(Item::Value(old)) = self.get_mut(key) {
+            *old = redecorated(old, v);
+        }
+    }
+
+    fn target(&mut self) -> Option<&mut dyn Fields> {
+        match self.get_mut("target") {
+            Some(Item::Table(t)) => Some(t),
+            Some(Item::Value(Value::InlineTable(t))) => Some(t),
+            _ => None,
+        }
+    }
+}
+
+impl Fields for InlineTable {
+    fn get(&self, key: &str) -> Option<String> {
+        InlineTable::get(self, key).and_then(Value::as_str).map(String::from)
+    }
+
+    fn set(&mut self, key: &str, v: &str) {
+        if let Some(old) = self.get_mut(key) {
+            *old = redecorated(old, v);
+        }
+    }
+
+    fn target(&mut self) -> Option<&mut dyn Fields> {
+        match self.get_mut("target") {
+            Some(Value::InlineTable(t)) => Some(t),
+            _ => None,
+        }
+    }
+}
+
+fn redecorated(old: &Value, v: &str) -> Value {
+    decorated(Value::from(v), old.decor().prefix(), old.decor().suffix())
+}
+
+/// Points kit `name` at path `id`, adding the kit if needed.
+pub fn use_kit(doc: &mut Document, name: &str, id: &str) -> Result<(), Error> {
+    match doc.as_table_mut().entry("kits") {
+        item @ Item::None => {
+            *item = Item::ArrayOfTables(ArrayOfTables::new());
+            use_kit(doc, name, id)
+        }
+        Item::ArrayOfTables(kits) => {
+            match (0..kits.len()).rev().find(|&idx| kits.get(idx).and_then(|k| k["name"].as_str()) == Some(name)) {
+                Some(idx) => set_str(kits.get_mut(idx).expect("expected array element"), "id", id),
+                None => {
+                    let kit = kits.append(Table::new());
+                    kit["name"] = value(name);
+                    kit["id"] = value(id);
+                }
+            }
+            Ok(())
+        }
+        Item::Value(Value::Array(kits)) => {
+            let mut kit = InlineTable::default();
+            kit.get_or_insert("name", name);
+            kit.get_or_insert("id", id);
+            upsert_inline(kits, name, kit, |k| set_inline_str(k, "id", id));
+            Ok(())
+        }
+        // `[kits]` keyed by name, an entry with its own path can't point elsewhere
+        Item::Table(kits) => match kits.get_mut(name) {
+            None => {
+                let mut kit = InlineTable::default();
+                kit.get_or_insert("id", id);
+                kit.fmt();
+                kits[name] = value(Value::InlineTable(kit));
+                Ok(())
+            }
+            Some(Item::Value(Value::InlineTable(kit))) if !kit.contains_key("path") && !kit.contains_key("target") => {
+                set_inline_str(kit, "id", id);
+                Ok(())
+            }
+            Some(Item::Table(kit)) if !kit.contains_key("path") && !kit.contains_key("target") => {
+                set_str(kit, "id", id);
+                Ok(())
+            }
+            Some(_) => Err(format_err!("kits.{} defines its own path, edit it by hand", name)),
+        },
+        _ => Err(format_err!("`kits` must be an array or a table")),
+    }
+}
+
+/// Maps binary `name` to `kit`, replacing an existing mapping in whichever of `kit` and `target` it uses.
+pub fn add_bin(doc: &mut Document, name: &str, kit: &str) -> Result<(), Error> {
+    let mut target = InlineTable::default();
+    target.get_or_insert("type", "Sdk");
+    target.get_or_insert("name", kit);
+    target.fmt();
+
+    match doc.as_table_mut().entry("bins") {
+        item @ Item::None => {
+            *item = Item::ArrayOfTables(ArrayOfTables::new());
+            add_bin(doc, name, kit)
+        }
+        Item::ArrayOfTables(bins) => {
+            match (0..bins.len()).rev().find(|&idx| bins.get(idx).and_then(|b| b["name"].as_str()) == Some(name)) {
+                Some(idx) => {
+                    let bin = bins.get_mut(idx).expect("expected array element");
+                    if bin.contains_key("kit") {
+                        bin.remove("target");
+                        set_str(bin, "kit", kit);
+                    } else {
+                        bin["target"] = value(Value::InlineTable(target));
+                    }
+                }
+                None => {
+                    let bin = bins.append(Table::new());
+                    bin["name"] = value(name);
+                    bin["target"] = value(Value::InlineTable(target));
+                }
+            }
+            Ok(())
+        }
+        Item::Value(Value::Array(bins)) => {
+            let mut bin = InlineTable::default();
+            bin.get_or_insert("name", name);
+            bin.get_or_insert("target", Value::InlineTable(target.clone()));
+            bin.fmt();
+            upsert_inline(bins, name, bin, |b| {
+                b.remove("target");
+                if b.contains_key("kit") {
+                    set_inline_str(b, "kit", kit);
+                } else {
+                    b.get_or_insert("target", Value::InlineTable(target.clone()));
+                    b.fmt();
+                }
+            });
+            Ok(())
+        }
+        Item::Table(bins) => {
+            match bins.get_mut(name) {
+                Some(Item::Value(Value::InlineTable(bin))) => {
+                    bin.remove("target");
+                    bin.remove("kit");
+                    bin.get_or_insert("kit", kit);
+                    bin.fmt();
+                }
+                Some(Item::Table(bin)) => {
+                    bin.remove("target");
+                    bin["kit"] = value(kit);
+                }
+                Some(Item::Value(old)) => *old = redecorated(old, kit),
+                _ => bins[name] = value(kit),
+            }
+            Ok(())
+        }
+        _ => Err(format_err!("`bins` must be an array or a table")),
+    }
+}
+
+/// Drops every mapping of binary `name`, returns how many there were.
+pub fn remove_bin(doc: &mut Document, name: &str) -> usize {
+    match doc.as_table_mut().entry("bins") {
+        Item::ArrayOfTables(bins) => {
+            let found: Vec<usize> = (0..bins.len())
+                .filter(|&idx| bins.get(idx).and_then(|b| b["name"].as_str()) == Some(name))
+                .collect();
+            found.iter().rev().for_each(|&idx| bins.remove(idx));
+            found.len()
+        }
+        Item::Value(Value::Array(bins)) => {
+            let found: Vec<usize> = (0..bins.len())
+                .filter(|&idx| inline_name(bins.get(idx)) == Some(name))
+                .collect();
+            found.iter().rev().for_each(|&idx| remove_inline(bins, idx));
+            found.len()
+        }
+        Item::Table(bins) => bins.remove(name).map(|_| 1).unwrap_or(0),
+        _ => 0,
+    }
+}
+
+fn inline_name(v: Option<&Value>) -> Option<&str> {
+    v.and_then(Value::as_inline_table).and_then(|t| t.get("name")).and_then(Value::as_str)
+}
+
+/// Updates the last entry called `name` or appends `new` in the style of the existing entries.
+fn upsert_inline(entries: &mut Array, name: &str, new: InlineTable, update: impl FnOnce(&mut InlineTable)) {
+    if let Some(idx) = (0..entries.len()).rev().find(|&idx| inline_name(entries.get(idx)) == Some(name)) {
+        let mut entry = entries.get(idx).expect("expected array element").clone();
+        if let Value::InlineTable(t) = &mut entry {
+            update(t);
+        }
+        entries.replace_formatted(idx, entry).expect("same value type");
+        return;
+    }
+
+    let mut new = new;
+    new.fmt();
+    match entries.len().checked_sub(1).and_then(|last| entries.get(last).cloned().map(|v| (last, v))) {
+        Some((last, prev)) => {
+            // the closing newline, if any, moves to the new last entry
+            let (prefix, suffix) = (String::from(prev.decor().prefix()), String::from(prev.decor().suffix()));
+            // indentation only, comments belong to the previous entry
+            let indent = match prefix.rfind('\n') {
+                Some(idx) => &prefix[idx..],
+                None => &prefix,
+            };
+            entries.replace_formatted(last, decorated(prev, &prefix, "")).expect("same value type");
+            entries.push_formatted(decorated(Value::InlineTable(new), indent, &suffix)).expect("same value type");
+        }
+        None => {
+            entries.push(Value::InlineTable(new)).expect("same value type");
+        }
+    }
+}
+
+fn remove_inline(entries: &mut Array, idx: usize) {
+    let removed = entries.remove(idx);
+    let suffix = String::from(removed.decor().suffix());
+    // keep the closing newline when the last entry goes
+    if idx == entries.len() && idx > 0 && suffix.contains('\n') {
+        let last = entries.get(idx - 1).expect("expected array element").clone();
+        let prefix = String::from(last.decor().prefix());
+        entries.replace_formatted(idx - 1, decorated(last, &prefix, &suffix)).expect("same value type");
+    }
+}
+
+fn set_str(table: &mut Table, key: &str, v: &str) {
+    match table.get_mut(key) {
+        Some(Item::Value(old)) => *old = redecorated(old, v),
+        _ => table[key] = value(v),
+    }
+}
+
+fn set_inline_str(table: &mut InlineTable, key: &str, v: &str) {
+    match table.get_mut(key) {
+        Some(old) => *old = redecorated(old, v),
+        None => {
+            table.get_or_insert(key, v);
+            table.fmt();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn migrated(content: &str) -> (String, Vec<String>) {
+        let mut doc = content.parse::<Document>().expect("valid TOML");
+        let (from, notes) = migrate(&mut doc).expect("migrates");
+        assert_eq!(from, 1);
+        (doc.to_string(), notes)
+    }
+
+    #[test]
+    fn migrates_targets_of_keyed_kits() {
+        let (out, notes) = migrated(concat!(
+            "[kits.jdk]\n",
+            "# newest 17\n",
+            "target = { type = \"HighestPrefix\", base = \"/usr/lib/jvm\", prefix = \"java-17\", path = \"/bin\" }\n",
+            "\n",
+            "[kits.node.target]\n",
+            "type = \"HighestPrefix\"\n",
+            "base = \"/opt\"\n",
+            "prefix = \"node-\"\n",
+            "path = \"/bin\"  # keep\n",
+        ));
+
+        assert!(out.contains("# newest 17\ntarget = { type = \"HighestPrefix\", base = \"/usr/lib/jvm\", prefix = \"java-17\", path = \"bin\" }"), "{}", out);
+        assert!(out.contains("path = \"bin\"  # keep"), "{}", out);
+        assert!(out.contains("version = 2"), "{}", out);
+        assert_eq!(notes, vec![
+            "kits.jdk.target.path: `/bin` became `bin`, it is relative to the picked entry",
+            "kits.node.target.path: `/bin` became `bin`, it is relative to the picked entry",
+        ]);
+    }
+
+    #[test]
+    fn notes_keyed_kit_paths_read_differently() {
+        let (_, notes) = migrated(concat!(
+            "[kits]\n",
+            "graal = \"~/.sdkman/candidates/java/current/bin\"\n",
+            "protoc = { path = \"tools/protoc/bin\" }\n",
+            "jdk = { target = { type = \"Dir\", path = \"${JAVA_HOME}/bin\" } }\n",
+            "sys = \"/usr/bin\"\n",
+        ));
+
+        assert_eq!(notes, vec![
+            "kits.graal: `~/.sdkman/candidates/java/current/bin` now starts in the home directory",
+            "kits.protoc.path: `tools/protoc/bin` is now relative to the config file in project configs",
+            "kits.jdk.target.path: `${JAVA_HOME}/bin` is now subject to variable expansion",
+        ]);
+    }
+
+    #[test]
+    fn migrates_paths_arrays() {
+        let (out, notes) = migrated(concat!(
+            "paths = [\n",
+            "  { id = \"jdk\", target = { type = \"HighestPrefix\", base = \"jvm\", prefix = \"java-\", path = \"/bin\" } },\n",
+            "]\n",
+        ));
+
+        assert!(out.contains("path = \"bin\" } },"), "{}", out);
+        assert_eq!(notes, vec![
+            "paths[jdk].target.path: `/bin` became `bin`, it is relative to the picked entry",
+            "paths[jdk].target.base: `jvm` is now relative to the config file in project configs",
+        ]);
+
+        let (_, notes) = migrated("[[paths]]\nid = \"node\"\ntarget = { type = \"Dir\", path = \"node/bin\" }\n");
+        assert_eq!(notes, vec!["paths[node].target.path: `node/bin` is now relative to the config file in project configs"]);
+    }
+
+    #[test]
+    fn leaves_current_files_alone() {
+        let mut doc = "version = 2\n[kits.jdk]\ntarget = { type = \"HighestPrefix\", base = \"/a\", prefix = \"b\", path = \"/bin\" }\n"
+            .parse::<Document>().unwrap();
+        let (from, notes) = migrate(&mut doc).unwrap();
+        assert_eq!(from, 2);
+        assert!(notes.is_empty());
+        assert!(doc.to_string().contains("path = \"/bin\""));
+    }
+
+    fn normalized(content: &str) -> String {
+        let mut doc = content.parse::<Document>().unwrap();
+        normalize(&mut doc);
+        doc.to_string()
+    }
+
+    #[test]
+    fn keeps_comments_of_inlined_tables() {
+        let content = r#"version = 2
+
+# kits
+[kits.jdk]
+id = "temurin" # pinned
+# the jdk
+[kits.jdk.target] # picked by prefix
+# newest 17
+type = "HighestPrefix"
+base = "/usr/lib/jvm"
+prefix = "java-17"
+path = "bin"
+
+[kits.node.target]
+type = "Dir"
+path = "/opt/node/bin"
+
+# first bin
+[[bins]]
+name = "java"
+# java's target
+[bins.target]
+type = "Sdk"
+name = "jdk"
+"#;
+        let expected = r#"version = 2
+
+# kits
+[kits.jdk]
+id = "temurin" # pinned
+# the jdk
+# picked by prefix
+# newest 17
+target = { type = "HighestPrefix", base = "/usr/lib/jvm", prefix = "java-17", path = "bin" }
+
+[kits.node]
+target = { type = "Dir", path = "/opt/node/bin" }
+
+# first bin
+[[bins]]
+name = "java"
+# java's target
+target = { type = "Sdk", name = "jdk" }
+"#;
+        assert_eq!(normalized(content), expected);
+        assert_eq!(normalized(expected), expected);
+        let config: crate::cfg::FileConfig = toml::from_str(expected).unwrap();
+        assert_eq!(config.kits.len(), 2);
+    }
+
+    fn edited(content: &str, edit: impl FnOnce(&mut Document)) -> String {
+        let mut doc = content.parse::<Document>().unwrap();
+        edit(&mut doc);
+        let out = doc.to_string();
+        toml::from_str::<crate::cfg::FileConfig>(&out).unwrap_or_else(|e| panic!("{}\n{}", e, out));
+        out
+    }
+
+    fn add(content: &str, name: &str, kit: &str) -> String {
+        edited(content, |doc| add_bin(doc, name, kit).unwrap())
+    }
+
+    #[test]
+    fn adds_bins_to_arrays_of_tables() {
+        let content = "version = 2\n\n[[bins]]\nname = \"java\"\nkit = \"jdk\" # lts\n\n[[bins]]\nname = \"node\"\ntarget = { type = \"Sdk\", name = \"node\" }\n";
+        assert_eq!(add(content, "java", "jdk21"), content.replace("\"jdk\" # lts", "\"jdk21\" # lts"));
+        assert_eq!(add(content, "node", "node18"), content.replace("name = \"node\" }", "name = \"node18\" }"));
+        assert_eq!(add(content, "sbt", "sbt"), format!("{}\n[[bins]]\nname = \"sbt\"\ntarget = {{ type = \"Sdk\", name = \"sbt\" }}\n", content));
+        assert_eq!(add("", "sbt", "sbt"), "\n[[bins]]\nname = \"sbt\"\ntarget = { type = \"Sdk\", name = \"sbt\" }\n");
+    }
+
+    #[test]
+    fn adds_bins_to_inline_arrays() {
+        let content = "version = 2\nbins = [\n  { name = \"java\", kit = \"jdk\" },\n  { name = \"node\", target = { type = \"Default\" } },\n]\n";
+        assert_eq!(add(content, "java", "jdk21"), content.replace("kit = \"jdk\"", "kit = \"jdk21\""));
+        assert_eq!(add(content, "node", "node18"), content.replace("{ type = \"Default\" }", "{ type = \"Sdk\", name = \"node18\" }"));
+        assert_eq!(
+            add(content, "sbt", "sbt"),
+            content.replace("},\n]", "},\n  { name = \"sbt\", target = { type = \"Sdk\", name = \"sbt\" } },\n]"),
+        );
+    }
+
+    #[test]
+    fn adds_bins_to_keyed_tables() {
+        let content = "version = 2\n\n[bins]\njava = \"jdk\" # lts\nnode = { kit = \"node\", exec = \"node\" }\n\n[bins.npm]\ntarget = { type = \"Default\" }\n";
+        assert_eq!(add(content, "java", "jdk21"), content.replace("\"jdk\" # lts", "\"jdk21\" # lts"));
+        assert_eq!(add(content, "node", "node18"), content.replace("{ kit = \"node\", exec = \"node\" }", "{ exec = \"node\", kit = \"node18\" }"));
+        assert_eq!(add(content, "npm", "node18"), content.replace("target = { type = \"Default\" }", "kit = \"node18\""));
+        assert_eq!(add(content, "sbt", "sbt"), content.replace("}\n\n[bins.npm]", "}\nsbt = \"sbt\"\n\n[bins.npm]"));
+    }
+
+    #[test]
+    fn removes_bins_in_every_layout() {
+        let tables = "[[bins]]\nname = \"java\"\nkit = \"jdk\"\n\n[[bins]]\nname = \"node\"\nkit = \"node\"\n";
+        let mut doc = tables.parse::<Document>().unwrap();
+        assert_eq!(remove_bin(&mut doc, "java"), 1);
+        assert_eq!(doc.to_string(), "\n[[bins]]\nname = \"node\"\nkit = \"node\"\n");
+
+        let inline = "bins = [\n  { name = \"java\", kit = \"jdk\" },\n  { name = \"java\", kit = \"jdk21\", when = { os = \"linux\" } },\n  { name = \"node\", kit = \"node\" },\n]\n";
+        let mut doc = inline.parse::<Document>().unwrap();
+        assert_eq!(remove_bin(&mut doc, "java"), 2);
+        assert_eq!(doc.to_string(), "bins = [\n  { name = \"node\", kit = \"node\" },\n]\n");
+        assert_eq!(remove_bin(&mut doc, "node"), 1);
+        assert_eq!(remove_bin(&mut doc, "node"), 0);
+
+        let keyed = "[bins]\njava = \"jdk\"\nnode = \"node\"\n";
+        let mut doc = keyed.parse::<Document>().unwrap();
+        assert_eq!(remove_bin(&mut doc, "java"), 1);
+        assert_eq!(doc.to_string(), "[bins]\nnode = \"node\"\n");
+    }
+
+    #[test]
+    fn points_kits_at_paths_in_every_layout() {
+        let use_kit = |content: &str, name: &str, id: &str| edited(content, |doc| use_kit(doc, name, id).unwrap());
+
+        let tables = "[[kits]]\nname = \"jdk\"\nid = \"jdk17\" # lts\n";
+        assert_eq!(use_kit(tables, "jdk", "jdk21"), tables.replace("jdk17", "jdk21"));
+        assert_eq!(use_kit(tables, "node", "node18"), format!("{}\n[[kits]]\nname = \"node\"\nid = \"node18\"\n", tables));
+
+        let inline = "kits = [ { name = \"jdk\", id = \"jdk17\" } ]\n";
+        assert_eq!(use_kit(inline, "jdk", "jdk21"), inline.replace("jdk17", "jdk21"));
+        assert_eq!(use_kit(inline, "node", "node18"), "kits = [ { name = \"jdk\", id = \"jdk17\" }, { name = \"node\", id = \"node18\" } ]\n");
+
+        let keyed = "[kits]\njdk = { id = \"jdk17\" }\n\n[kits.graal]\nid = \"graal22\"\n";
+        assert_eq!(use_kit(keyed, "jdk", "jdk21"), keyed.replace("jdk17", "jdk21"));
+        assert_eq!(use_kit(keyed, "graal", "graal23"), keyed.replace("graal22", "graal23"));
+        assert_eq!(use_kit(keyed, "node", "node18"), keyed.replace("}\n\n[kits.graal]", "}\nnode = { id = \"node18\" }\n\n[kits.graal]"));
+
+        let mut doc = "[kits]\njdk = \"/usr/lib/jvm/bin\"\n".parse::<Document>().unwrap();
+        assert!(super::use_kit(&mut doc, "jdk", "jdk21").unwrap_err().to_string().contains("kits.jdk defines its own path"));
+    }
+}
@@ -2,50 +2,58 @@ use std::{
     io::Read,
 };
 use std::env;
-use std::ffi::CString;
+use std::ffi::{CString, OsStr, OsString};
 use std::fs::File;
 use std::io::BufReader;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::PathBuf;
 
-use libc;
-use crate::cfg::Config;
+use crate::cfg::Resolution;
 
 
-pub fn do_passthrough(config: Config, name: &str) -> () {
-    let resolved = config.resolve();
+pub fn do_passthrough(resolution: Resolution, name: &OsStr) {
 
+    let env_args: Vec<OsString> = env::args_os().collect();
+    let (invoked, just_args) = env_args.split_at(1);
+    let just_args_vec = just_args.to_vec();
+
+    if let Some(warning) = &resolution.warning {
+        eprintln!("binlink: {}", warning);
+    }
 
-    let bin: String = match resolved.bins.get(name) {
-        Some(path) => {
-            log::debug!("{} is configured: {}", name, path);
-            find_main(name, path)
+    let (bin, argv0): (PathBuf, OsString) = match resolution.target {
+        Some(target) => {
+            log::debug!("{:?} is configured: {:?}", name, target);
+            let argv0 = target.argv0.select(&invoked[0], &target.path);
+            (target.path, argv0)
         }
         None => {
-            log::debug!("{} not configured, matching on $PATH", name);
-            find_fallback(name)
+            log::debug!("{:?} not configured, matching on $PATH", name);
+            let bin = find_fallback(name);
+            let argv0 = bin.clone().into_os_string();
+            (bin, argv0)
         }
     };
 
-    log::info!("{} resolved as {}", name, bin);
+    log::info!("{:?} resolved as {:?}", name, bin);
 
-    let mut f = BufReader::new(File::open(bin.clone()).expect("open failed"));
+    let mut f = BufReader::new(File::open(&bin).expect("open failed"));
     let mut buffer = [0; 2];
     f.read_exact(&mut buffer).expect("cannot check file type");
-    let has_shebang = buffer[0] == '#' as u8 && buffer[1] == '!' as u8;
-
-    let env_args: Vec<String> = env::args().collect();
-    let (_, just_args) = env_args.split_at(1);
-    let just_args_vec = just_args.to_vec();
+    let has_shebang = buffer[0] == b'#' && buffer[1] == b'!';
 
-    let (sb_bin, args): (String, Vec<String>) = if has_shebang {
-        let cmd: Vec<String> = vec![vec![bin], just_args_vec].into_iter().flatten().map(|s| format!("'{}'", s)).collect();
-        (String::from("/bin/sh"), vec![vec![String::from("/bin/sh"), String::from("-c"), cmd.join(" ")]].into_iter().flatten().collect())
+    // the kernel hands scripts to their interpreter by path, so argv[0] only matters for direct exec;
+    // passing arguments positionally keeps them out of shell parsing
+    let (sb_bin, args): (OsString, Vec<OsString>) = if has_shebang {
+        let sh = vec![OsString::from("/bin/sh"), OsString::from("-c"), OsString::from("exec \"$0\" \"$@\""), bin.into_os_string()];
+        (OsString::from("/bin/sh"), vec![sh, just_args_vec].into_iter().flatten().collect())
     } else {
-        (bin.clone(), vec![vec![bin], just_args_vec].into_iter().flatten().collect())
+        (bin.into_os_string(), vec![vec![argv0], just_args_vec].into_iter().flatten().collect())
     };
 
-    log::info!("about to run: {}, argv: {:#?}", sb_bin, args);
+    log::info!("about to run: {:?}, argv: {:#?}", sb_bin, args);
 
-    let c_str_1 = CString::new(sb_bin.clone()).unwrap();
+    let c_str_1 = CString::new(sb_bin.as_bytes()).unwrap();
 
     let argv = make_cstring_array(args.clone());
     let envp = make_cstring_array(make_env());
@@ -59,63 +67,47 @@ pub fn do_passthrough(config: Config, name: &str) -> () {
             panic!("Impossible: Launcher continued after successful execve")
         }
         c => {
-            panic!(format!("execve failed with code {}; command: `{} {}`", c, sb_bin, args.join(" ")))
+            let cmd: Vec<_> = args.iter().map(|a| a.to_string_lossy()).collect();
+            panic!("execve failed with code {}; command: `{}`", c, cmd.join(" "))
         }
     }
 }
 
 
-fn make_env() -> Vec<String> {
-    return env::vars().map(|(key, value)| format!("{}={}", key, value)).collect();
+fn make_env() -> Vec<OsString> {
+    env::vars_os().map(|(key, value)| {
+        let mut pair = key;
+        pair.push("=");
+        pair.push(value);
+        pair
+    }).collect()
 }
 
-fn find_fallback(name: &str) -> String {
-    let cwd = env::current_dir().expect("cannot get cwd");
+fn find_fallback(name: &OsStr) -> PathBuf {
+    // only relative $PATH entries depend on it, so a deleted cwd must not break the lookup
+    let cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("/"));
 
     let path = env::var_os("PATH").map(|p| {
         let split = env::split_paths(&p);
-        env::join_paths(split.filter(|p| !p.to_str().expect("???").contains("binlinks"))).expect("???")
+        env::join_paths(split.filter(|p| !p.to_string_lossy().contains("binlinks"))).expect("???")
     });
 
-    let result = which::which_in(name, path, &cwd);
-    match result {
-        Ok(bin) => {
-            match bin.to_str() {
-                Some(path) => { path.to_owned() }
-                _ =>
-                    panic!(format!("Cannot get binary name for: {}", name))
-            }
-        }
-        _ => {
-            panic!(format!("Cannot find binary in $PATH: {}", name))
-        }
-    }
-}
-
-fn find_main(name: &str, target: &str) -> String {
-    let result = which::which_in(name, Some(target), target);
-    match result {
-        Ok(bin) => {
-            match bin.to_str() {
-                Some(path) => { path.to_owned() }
-                _ =>
-                    panic!(format!("Cannot get binary name for: {}", name))
-            }
-        }
-        _ => {
-            panic!(format!("Cannot find binary in $PATH: {}", name))
-        }
+    match which::which_in(name, path, &cwd) {
+        Ok(bin) => bin,
+        _ => panic!("Cannot find binary in $PATH: {:?}", name),
     }
 }
 
 struct UnsafeStrVec {
+    // owns the strings `tmp` points into
+    #[allow(dead_code)]
     base: Vec<CString>,
-    tmp: Vec<*const i8>,
+    tmp: Vec<*const libc::c_char>,
 }
 
-fn make_cstring_array(argv: Vec<String>) -> UnsafeStrVec {
-    let cstr_argv: Vec<_> = argv.iter()
-        .map(|arg| CString::new(arg.as_str()).unwrap())
+fn make_cstring_array(argv: Vec<OsString>) -> UnsafeStrVec {
+    let cstr_argv: Vec<_> = argv.into_iter()
+        .map(|arg| CString::new(arg.into_vec()).unwrap())
         .collect();
 
     let mut p_argv: Vec<_> = cstr_argv.iter() // do NOT into_iter()
@@ -124,11 +116,8 @@ fn make_cstring_array(argv: Vec<String>) -> UnsafeStrVec {
 
     p_argv.push(std::ptr::null());
 
-    let out = UnsafeStrVec {
+    UnsafeStrVec {
         base: cstr_argv,
         tmp: p_argv,
-    };
-
-
-    return out;
+    }
 }
@@ -0,0 +1,220 @@
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use failure::{format_err, Error};
+
+use crate::cache::Stamp;
+use crate::cond::Facts;
+
+/// Built-in variables available to path-bearing config fields.
+pub struct Vars<'a> {
+    /// directory of the file which declared the field
+    pub config_dir: Option<&'a Path>,
+    /// directory of the innermost project config
+    pub project_root: Option<&'a Path>,
+    pub facts: &'a Facts,
+    /// relative results are taken relative to this directory
+    pub relative_to: Option<&'a Path>,
+    /// version 1 configs: values are used as written, relative ones against cwd
+    pub literal: bool,
+}
+
+/// Expands a leading `~`, `${VAR}`, `${VAR:-default}` and built-ins such as `{config_dir}`.
+///
+/// A default starting the value may itself start with `~`, as in `${SDKMAN_DIR:-~/.sdkman}`.
+///
+/// `field` names the config field in errors, relative results are anchored at `vars.relative_to`. Environment variables read are recorded in `watched`.
+pub fn expand(value: &str, field: &str, vars: &Vars, watched: &mut Vec<Stamp>) -> Result<PathBuf, Error> {
+    if vars.literal {
+        return Ok(PathBuf::from(value));
+    }
+
+    let mut out = OsString::new();
+
+    let mut rest = value;
+    if is_home(rest) {
+        out.push(home_dir(field)?);
+        rest = &rest[1..];
+    }
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("${") {
+            let end = after.find('}')
+                .ok_or_else(|| format_err!("unterminated `${{` in `{}`: {}", field, value))?;
+            let (name, default) = match after[..end].find(":-") {
+                Some(idx) => (&after[..idx], Some(&after[idx + 2..end])),
+                None => (&after[..end], None),
+            };
+
+            watched.push(Stamp::var(name));
+            match (env::var_os(name).filter(|v| !v.is_empty()), default) {
+                (Some(v), _) => out.push(v),
+                (None, Some(d)) if out.is_empty() && is_home(d) => {
+                    out.push(home_dir(field)?);
+                    out.push(&d[1..]);
+                }
+                (None, Some(d)) => out.push(d),
+                (None, None) => return Err(format_err!("undefined variable `{}` in `{}`: {}", name, field, value)),
+            }
+            rest = &after[end + 1..];
+        } else if let Some((name, after)) = builtin(rest) {
+            let expanded: OsString = match name {
+                "config_dir" => vars.config_dir.map(|d| d.as_os_str().to_owned()),
+                "project_root" => vars.project_root.map(|d| d.as_os_str().to_owned()),
+                "os" => Some(OsString::from(&vars.facts.os)),
+                "arch" => Some(OsString::from(&vars.facts.arch)),
+                "hostname" => Some(OsString::from(&vars.facts.hostname)),
+                other => return Err(format_err!("unknown variable `{{{}}}` in `{}`: {}", other, field, value)),
+            }.ok_or_else(|| format_err!("variable `{{{}}}` is not defined for `{}`: {}", name, field, value))?;
+            out.push(expanded);
+            rest = after;
+        } else {
+            let first = rest.chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+            let next = rest[first..].find(['$', '{']).map(|i| i + first).unwrap_or_else(|| rest.len());
+            out.push(&rest[..next]);
+            rest = &rest[next..];
+        }
+    }
+
+    let out = PathBuf::from(out);
+    Ok(match vars.relative_to {
+        Some(dir) if out.is_relative() => dir.join(out),
+        _ => out,
+    })
+}
+
+fn is_home(value: &str) -> bool {
+    value == "~" || value.starts_with("~/")
+}
+
+fn home_dir(field: &str) -> Result<PathBuf, Error> {
+    dirs::home_dir().ok_or_else(|| format_err!("cannot expand `~` in `{}`: no home directory", field))
+}
+
+/// `{name}` at the start of `s`, returns the name and what follows.
+fn builtin(s: &str) -> Option<(&str, &str)> {
+    let after = s.strip_prefix('{')?;
+    let end = after.find('}')?;
+    let name = &after[..end];
+    if !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c == '_') {
+        Some((name, &after[end + 1..]))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn facts() -> Facts {
+        Facts { os: String::from("linux"), arch: String::from("x86_64"), hostname: String::from("ci-1") }
+    }
+
+    fn expand_with(value: &str, relative_to: Option<&Path>) -> Result<PathBuf, Error> {
+        let facts = facts();
+        let vars = Vars {
+            config_dir: Some(Path::new("/work/repo/conf")),
+            project_root: Some(Path::new("/work/repo")),
+            facts: &facts,
+            relative_to,
+            literal: false,
+        };
+        expand(value, "paths[x].target.path", &vars, &mut Vec::new())
+    }
+
+    #[test]
+    fn expands_leading_tilde_only() {
+        let home = dirs::home_dir().expect("home directory");
+        assert_eq!(expand_with("~", None).unwrap(), home);
+        assert_eq!(expand_with("~/.sdkman/bin", None).unwrap(), home.join(".sdkman/bin"));
+        assert_eq!(expand_with("~other/bin", None).unwrap(), PathBuf::from("~other/bin"));
+        assert_eq!(expand_with("/opt/~/bin", None).unwrap(), PathBuf::from("/opt/~/bin"));
+    }
+
+    #[test]
+    fn expands_variables_and_defaults() {
+        env::set_var("BINLINK_TEST_INTERP_SET", "/opt/java");
+        env::set_var("BINLINK_TEST_INTERP_EMPTY", "");
+        env::remove_var("BINLINK_TEST_INTERP_UNSET");
+
+        assert_eq!(expand_with("${BINLINK_TEST_INTERP_SET}/bin", None).unwrap(), PathBuf::from("/opt/java/bin"));
+        assert_eq!(expand_with("${BINLINK_TEST_INTERP_SET:-/usr}/bin", None).unwrap(), PathBuf::from("/opt/java/bin"));
+        assert_eq!(expand_with("${BINLINK_TEST_INTERP_UNSET:-/usr}/bin", None).unwrap(), PathBuf::from("/usr/bin"));
+        // empty counts as unset
+        assert_eq!(expand_with("${BINLINK_TEST_INTERP_EMPTY:-/usr}/bin", None).unwrap(), PathBuf::from("/usr/bin"));
+        assert_eq!(expand_with("${BINLINK_TEST_INTERP_UNSET:-}/bin", None).unwrap(), PathBuf::from("/bin"));
+        // a leading default may start in the home directory
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(expand_with("${BINLINK_TEST_INTERP_UNSET:-~/.sdkman}/candidates", None).unwrap(), home.join(".sdkman/candidates"));
+        assert_eq!(expand_with("${BINLINK_TEST_INTERP_UNSET:-~}/x", None).unwrap(), home.join("x"));
+        assert_eq!(expand_with("${BINLINK_TEST_INTERP_SET:-~/.sdkman}/bin", None).unwrap(), PathBuf::from("/opt/java/bin"));
+        assert_eq!(expand_with("/opt/${BINLINK_TEST_INTERP_UNSET:-~/x}", None).unwrap(), PathBuf::from("/opt/~/x"));
+    }
+
+    #[test]
+    fn rejects_undefined_and_empty_variables_without_default() {
+        env::set_var("BINLINK_TEST_INTERP_EMPTY2", "");
+        env::remove_var("BINLINK_TEST_INTERP_UNSET2");
+
+        let err = expand_with("${BINLINK_TEST_INTERP_UNSET2}/bin", None).unwrap_err().to_string();
+        assert!(err.contains("undefined variable `BINLINK_TEST_INTERP_UNSET2`"), "{}", err);
+        assert!(err.contains("paths[x].target.path"), "{}", err);
+        assert!(expand_with("${BINLINK_TEST_INTERP_EMPTY2}/bin", None).is_err());
+        assert!(expand_with("${BINLINK_TEST_INTERP_UNSET2/bin", None).unwrap_err().to_string().contains("unterminated"));
+    }
+
+    #[test]
+    fn records_read_variables() {
+        let facts = facts();
+        let vars = Vars { config_dir: None, project_root: None, facts: &facts, relative_to: None, literal: false };
+        let mut watched = Vec::new();
+        expand("${BINLINK_TEST_INTERP_WATCHED:-/x}", "include", &vars, &mut watched).unwrap();
+        assert_eq!(watched, vec![Stamp::var("BINLINK_TEST_INTERP_WATCHED")]);
+    }
+
+    #[test]
+    fn expands_builtins() {
+        assert_eq!(expand_with("{config_dir}/tools", None).unwrap(), PathBuf::from("/work/repo/conf/tools"));
+        assert_eq!(expand_with("{project_root}/tools", None).unwrap(), PathBuf::from("/work/repo/tools"));
+        assert_eq!(expand_with("/opt/jdk-{os}-{arch}/{hostname}", None).unwrap(), PathBuf::from("/opt/jdk-linux-x86_64/ci-1"));
+    }
+
+    #[test]
+    fn rejects_unknown_and_unavailable_builtins() {
+        let err = expand_with("{confdir}/tools", None).unwrap_err().to_string();
+        assert!(err.contains("unknown variable `{confdir}`"), "{}", err);
+
+        let facts = facts();
+        let vars = Vars { config_dir: None, project_root: None, facts: &facts, relative_to: None, literal: false };
+        let err = expand("{project_root}/tools", "include", &vars, &mut Vec::new()).unwrap_err().to_string();
+        assert!(err.contains("`{project_root}` is not defined"), "{}", err);
+    }
+
+    #[test]
+    fn keeps_braces_which_are_not_builtins() {
+        assert_eq!(expand_with("/opt/{Weird Name}/a{", None).unwrap(), PathBuf::from("/opt/{Weird Name}/a{"));
+        assert_eq!(expand_with("/opt/$HOME", None).unwrap(), PathBuf::from("/opt/$HOME"));
+    }
+
+    #[test]
+    fn anchors_relative_results() {
+        let base = Path::new("/work/repo");
+        assert_eq!(expand_with("tools/bin", Some(base)).unwrap(), PathBuf::from("/work/repo/tools/bin"));
+        assert_eq!(expand_with("/opt/bin", Some(base)).unwrap(), PathBuf::from("/opt/bin"));
+        assert_eq!(expand_with("tools/bin", None).unwrap(), PathBuf::from("tools/bin"));
+        // an expanded absolute value isn't anchored again
+        assert_eq!(expand_with("{config_dir}/bin", Some(Path::new("/elsewhere"))).unwrap(), PathBuf::from("/work/repo/conf/bin"));
+    }
+
+    #[test]
+    fn keeps_version_1_values_as_written() {
+        let facts = facts();
+        let vars = Vars { config_dir: None, project_root: None, facts: &facts, relative_to: Some(Path::new("/work")), literal: true };
+        let mut watched = Vec::new();
+        assert_eq!(expand("~/jdk/${HOME}/{os}", "x", &vars, &mut watched).unwrap(), PathBuf::from("~/jdk/${HOME}/{os}"));
+        assert_eq!(expand("jdk/bin", "x", &vars, &mut watched).unwrap(), PathBuf::from("jdk/bin"));
+        assert!(watched.is_empty());
+    }
+}
@@ -1,25 +1,38 @@
 use std::{
     path::Path,
 };
+use std::collections::HashSet;
 use std::ffi::OsString;
 use std::fs;
 use std::os::unix::fs as ufs;
 
-use toml_edit::{ArrayOfTables, Document, InlineTable, value, Value};
+use toml_edit::Document;
 
 
 use std::path::PathBuf;
 use std::env;
 use env_logger::Builder;
-use log;
 
-use crate::cfg::GlobalConfig;
+use crate::cfg::{CONFIG_VERSION, FileConfig, LayerKind, Resolution};
+use crate::cfgreader::Format;
+use crate::shorthand::RawConfig;
 
+mod cache;
 mod cfg;
 mod cfgreader;
 mod cli;
+mod cond;
+mod detect;
+mod diag;
+mod edit;
+#[cfg(feature = "download")]
 mod dl;
 mod execv;
+mod interp;
+mod pins;
+mod shorthand;
+#[cfg(test)]
+mod testutil;
 
 
 fn main() {
@@ -28,30 +41,100 @@ fn main() {
         .init();
 
     let bin_path = std::env::current_exe()
-        .map(|exe|
-            exe.file_name()
-                .map(OsString::from)
-                .and_then(|s| s.to_str().map(|s| s.to_owned()))
-        );
+        .map(|exe| exe.file_name().map(OsString::from));
     let full_bin_path = std::env::current_exe();
 
     log::debug!("binlink: self={:#?}, path={:#?}", bin_path, full_bin_path);
 
     match (bin_path, full_bin_path) {
         (Ok(Some(e)), Ok(p)) => {
-            match e.as_str() {
-                "binlink" => {
+            match e.to_str() {
+                Some("binlink") => {
                     let opts = cli::Args::make();
                     match opts.matches().subcommand_name() {
                         Some("link") => create_links(&p),
-                        Some("example") => show_example(),
+                        Some("example") => {
+                            let matches = opts.matches();
+                            let format = matches.subcommand_matches("example")
+                                .and_then(|m| m.value_of("format"))
+                                .and_then(Format::parse)
+                                .unwrap_or(Format::Toml);
+                            show_example(format);
+                        }
+                        Some("fmt") => {
+                            let matches = opts.matches();
+                            format_config(matches.subcommand_matches("fmt").and_then(|m| m.value_of("path")));
+                        }
+                        Some("migrate") => {
+                            let matches = opts.matches();
+                            migrate_config(matches.subcommand_matches("migrate").and_then(|m| m.value_of("path")));
+                        }
+                        Some("init") => {
+                            let matches = opts.matches();
+                            let sub = matches.subcommand_matches("init").expect("init matches");
+                            init_config(sub.is_present("print"), sub.is_present("force"), &p);
+                        }
+                        Some("use") => {
+                            let matches = opts.matches();
+                            let sub = matches.subcommand_matches("use").expect("use matches");
+                            let mappings: Vec<&str> = sub.values_of("mapping").map(|v| v.collect()).unwrap_or_default();
+                            edit_config(sub.is_present("global"), &p, |doc| {
+                                mappings.iter().try_for_each(|m| {
+                                    let (kit, id) = m.split_once('=')
+                                        .ok_or_else(|| failure::format_err!("expected kit=path-id, got `{}`", m))?;
+                                    edit::use_kit(doc, kit, id)
+                                })
+                            });
+                        }
+                        Some("add-bin") => {
+                            let matches = opts.matches();
+                            let sub = matches.subcommand_matches("add-bin").expect("add-bin matches");
+                            let name = sub.value_of("name").expect("name is required");
+                            let kit = sub.value_of("kit").expect("kit is required");
+                            edit_config(sub.is_present("global"), &p, |doc| edit::add_bin(doc, name, kit));
+                        }
+                        Some("rm-bin") => {
+                            let matches = opts.matches();
+                            let sub = matches.subcommand_matches("rm-bin").expect("rm-bin matches");
+                            let name = sub.value_of("name").expect("name is required");
+                            edit_config(sub.is_present("global"), &p, |doc| match edit::remove_bin(doc, name) {
+                                0 => Err(failure::format_err!("`{}` is not mapped in this config", name)),
+                                _ => Ok(()),
+                            });
+                        }
+                        Some("import") => import_pins(&p),
+                        Some("export") => export_tool_versions(),
+                        Some("schema") => show_schema(),
+                        Some("which") => {
+                            let matches = opts.matches();
+                            show_which(matches.subcommand_matches("which").and_then(|m| m.value_of("name")));
+                        }
+                        #[cfg(feature = "download")]
+                        Some("download") => {
+                            let matches = opts.matches();
+                            let url = matches.subcommand_matches("download")
+                                .and_then(|m| m.value_of("url"))
+                                .expect("url is required");
+                            if let Err(e) = dl::download(url) {
+                                panic!("download failed: {:?}", e);
+                            }
+                        }
+                        Some("cache") => {
+                            match opts.matches().subcommand() {
+                                Some(("cache", sub)) if sub.subcommand_name() == Some("clear") => cache::clear(),
+                                _ => opts.print_help(),
+                            }
+                        }
                         _ => opts.print_help(),
 
                     }
                 }
-                o => {
-                    let config = cfgreader::get_config();
-                    execv::do_passthrough(config, o);
+                Some(o) => {
+                    execv::do_passthrough(resolve_passthrough(o), &e);
+                }
+                None => {
+                    // config keys are UTF-8, so such a name can only come from $PATH
+                    execv::do_passthrough(Resolution::unresolved(), &e);
                 }
             }
         }
@@ -59,12 +142,101 @@ fn main() {
             panic!("Cannot determine binary name, got: ${:#?}", o);
         }
     }
+}
+
+/// Config or resolution errors degrade to a plain `$PATH` lookup unless strict mode is on,
+/// so one broken `.binlink.toml` doesn't take every linked tool down with it.
+/// A kit lacking a binary under an explicit `on_missing = { type = "Error" }` stays fatal.
+fn resolve_passthrough(name: &str) -> Resolution {
+    if cfgreader::is_disabled() {
+        log::debug!("{} is set, bypassing config", cfgreader::BINLINK_DISABLE_EVAR);
+        return Resolution::unresolved();
+    }
+
+    match cache::resolve(name) {
+        Ok(resolution) => resolution,
+        Err(e) if e.downcast_ref::<cfg::MissingBinary>().is_some() || cfgreader::is_strict() => {
+            panic!("cannot resolve {}: {}", name, e);
+        }
+        Err(e) => {
+            eprintln!("binlink: cannot resolve {}, falling back to $PATH: {}", name, e);
+            Resolution::unresolved()
+        }
+    }
+}
+
+fn show_which(name: Option<&str>) {
+    let paths = cfgreader::get_config_paths().unwrap_or_else(|e| panic!("{}", e));
+
+    println!("config stack, lowest priority first:");
+    if paths.sources.is_empty() {
+        println!("  (no config files found)");
+    }
+    let mut broken_layer = false;
+    paths.sources.iter().for_each(|s| {
+        println!("  {:<8} {}", s.kind.name(), s.path.display());
+        if s.kind == LayerKind::Pins {
+            pins::read(&s.path).iter().for_each(|p| println!("  {:<8}   {} {}", "", p.tool, p.version));
+            return;
+        }
+        match cfgreader::parse_layer(s, paths.project_root()) {
+            Ok(layer) => layer.includes.iter().for_each(|i| println!("  {:<8}   includes {}", "", i.display())),
+            Err(e) => {
+                broken_layer = true;
+                println!("  {:<8}   error: {}", "", e);
+            }
+        }
+    });
+
+    let parsed = cfgreader::parse_config(&paths);
+    // errors spanning layers, e.g. a bin set none of them defines
+    if let (Err(e), false) = (&parsed, broken_layer) {
+        println!("error: {}", e);
+    }
 
-    // let result = download("https://github.com/graalvm/graalvm-ce-builds/releases/download/vm-20.2.0/graalvm-ce-java11-darwin-amd64-20.2.0.tar.gz");
-    // match result {
-    //     Ok(v) => println!("ok: {:?}", v),
-    //     Err(e) => println!("error: {:?}", e),
-    // }
+    let has_pins = paths.sources.iter().any(|s| s.kind == LayerKind::Pins && !pins::always_read(&s.path));
+    let reads_pins = parsed.as_ref().map(|c| cfgreader::reads_pin_files(&c.layers));
+    if has_pins && !reads_pins.unwrap_or(false) {
+        println!("version files are ignored, set `pin_files = true` or export {}", cfgreader::BINLINK_PIN_FILES_EVAR);
+    }
+
+    if let Ok(config) = &parsed {
+        let mut literal: Vec<&PathBuf> = config.layers.iter()
+            .flat_map(|l| l.config.paths().iter())
+            .filter(|p| p.literal)
+            .filter_map(|p| p.origin.as_ref())
+            .collect();
+        literal.dedup();
+        literal.iter().for_each(|p| println!("{} is config version 1, kit paths are used as written, see `binlink migrate`", p.display()));
+    }
+
+    if let Some(name) = name {
+        let config = match parsed {
+            Ok(config) => config,
+            Err(_) => {
+                println!("{} cannot be resolved while the config is broken", name);
+                return;
+            }
+        };
+
+        match config.origin_of(name) {
+            Some(layer) => println!("{} is mapped in {} config {}", name, layer.kind.name(), layer.path.display()),
+            None => println!("{} is not mapped", name),
+        }
+
+        match config.resolve(name) {
+            Ok(Resolution { target: Some(target), .. }) => {
+                println!("{} -> {}", name, target.path.display());
+            }
+            Ok(resolution) => {
+                resolution.warning.iter().for_each(|w| println!("warning: {}", w));
+                println!("{} -> $PATH", name);
+            }
+            Err(e) => {
+                println!("{} cannot be resolved: {}", name, e);
+            }
+        }
+    }
 }
 
 fn create_links(p: &PathBuf) {
@@ -77,69 +249,193 @@ fn create_links(p: &PathBuf) {
             println!("cannot create {}: {:#?}", dir, e);
         }
     }
-    let config = cfgreader::get_config();
-    let resolved = config.resolve();
-    resolved.names.iter().for_each(|k| {
+    let config = cfgreader::get_config().unwrap_or_else(|e| panic!("{}", e));
+    config.names().iter().for_each(|k| {
         let target = Path::new(dir).join(Path::new(k));
-        match fs::remove_file(&target) {
-            _ => {}
-        }
+        // a stale link may or may not be there
+        let _ = fs::remove_file(&target);
 
         let bin = Path::new(&p);
-        match ufs::symlink(&bin, &target) {
+        match ufs::symlink(bin, &target) {
             Ok(_) => {
                 println!("Created: {:#?} -> {:#?}", &target, &bin);
             }
             Err(e) => {
-                panic!(format!("cannot symlink {}: {:#?}", k, e));
+                panic!("cannot symlink {}: {:#?}", k, e);
             }
         }
     })
 }
 
-fn show_example() {
-    let example = GlobalConfig::example();
+fn show_example(format: Format) {
+    let example = FileConfig::example();
 
-    match toml::to_string_pretty(&example) {
-        Ok(tout) => {
-            let mut doc = tout.parse::<Document>().expect("invalid doc");
+    let rendered = match format {
+        Format::Toml => example_toml(&example),
+        Format::Yaml => serde_yaml::to_string(&example).map_err(|e| e.to_string()),
+        Format::Json => serde_json::to_string_pretty(&example).map_err(|e| e.to_string()),
+    };
 
-            {
-                let bins_arr = doc["bins"].as_array_of_tables_mut().expect("xxx");
-                make_inline_tbls(bins_arr)
-            }
+    match rendered {
+        Ok(out) => println!("{}", out.trim_end()),
+        Err(e) => panic!("error: {:#?}", e),
+    }
+}
+
+fn show_schema() {
+    let mut schema = schemars::schema_for!(RawConfig);
+    schema.schema.metadata().title = Some(String::from("binlink config"));
 
-            {
-                let paths_arr = doc["paths"].as_array_of_tables_mut().expect("xxx");
-                make_inline_tbls(paths_arr)
+    match serde_json::to_string_pretty(&schema) {
+        Ok(out) => println!("{}", out),
+        Err(e) => panic!("error: {:#?}", e),
+    }
+}
+
+/// TOML with inline `target` tables, which reads much better than nested `[bins.target]` sections.
+fn example_toml(example: &FileConfig) -> Result<String, String> {
+    let tout = toml::to_string_pretty(example).map_err(|e| e.to_string())?;
+    let mut doc = tout.parse::<Document>().expect("invalid doc");
+    edit::normalize(&mut doc);
+    Ok(doc.to_string())
+}
+
+/// `path` or the config closest to cwd.
+fn config_to_edit(path: Option<&str>) -> PathBuf {
+    match path {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let paths = cfgreader::get_config_paths().unwrap_or_else(|e| panic!("{}", e));
+            match paths.sources.iter().rev().find(|s| s.kind != LayerKind::Pins) {
+                Some(source) => source.path.clone(),
+                None => panic!("no config found, pass a path"),
             }
-            println!("{}", doc.to_string());
         }
-        Err(e) => {
-            panic!(format!("error: {:#?}", e));
+    }
+}
+
+fn init_config(print: bool, force: bool, exe: &PathBuf) {
+    let kits = detect::scan();
+    let rendered = detect::render(&kits);
+    if let Err(e) = toml::from_str::<FileConfig>(&rendered) {
+        panic!("generated an invalid config: {}\n{}", e, rendered);
+    }
+
+    if print {
+        print!("{}", rendered);
+        return;
+    }
+
+    let path = cfgreader::user_config_target().unwrap_or_else(|e| panic!("{}", e));
+    if path.exists() && !force {
+        panic!("{} already exists, pass --force to overwrite or --print to compare", path.display());
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).unwrap_or_else(|e| panic!("cannot create {}: {}", dir.display(), e));
+    }
+    fs::write(&path, rendered).unwrap_or_else(|e| panic!("cannot write {}: {}", path.display(), e));
+
+    println!("wrote {} with {} kits", path.display(), kits.len());
+    create_links(exe);
+}
+
+/// Applies `change` to the user or innermost project config, links names it introduced.
+fn edit_config<F>(global: bool, exe: &PathBuf, change: F)
+    where F: FnOnce(&mut Document) -> Result<(), failure::Error>
+{
+    let path = if global { cfgreader::user_config_target() } else { cfgreader::local_config_target() }
+        .unwrap_or_else(|e| panic!("{}", e));
+    let known: HashSet<String> = cfgreader::get_config().map(|c| c.names()).unwrap_or_default();
+
+    let mut doc = edit::load_or_new(&path).unwrap_or_else(|e| panic!("{}", e));
+    change(&mut doc).unwrap_or_else(|e| panic!("cannot edit {}: {}", path.display(), e));
+    edit::save(&path, &doc).unwrap_or_else(|e| panic!("{}", e));
+    println!("updated {}", path.display());
+
+    match cfgreader::get_config() {
+        Ok(config) if !config.names().is_subset(&known) => create_links(exe),
+        Ok(_) => (),
+        Err(e) => println!("warning: config is broken after the edit: {}", e),
+    }
+}
+
+/// Writes kit mappings for version files in cwd into the project config.
+fn import_pins(exe: &PathBuf) {
+    let cwd = env::current_dir().unwrap_or_else(|e| panic!("cannot determine current directory: {}", e));
+    let pins = pins::read_dir(&cwd);
+    if pins.is_empty() {
+        panic!("none of {} in {}", pins::PIN_FILES.join(", "), cwd.display());
+    }
+
+    let mut config = cfgreader::get_config().unwrap_or_else(|e| panic!("{}", e));
+    // what read-through made of the same files would match itself
+    config.layers.retain(|l| l.kind != LayerKind::Pins);
+
+    let mut found = Vec::new();
+    for pin in &pins {
+        match pin.resolve(&config) {
+            Ok((kit, id)) => {
+                println!("{} {} -> kit `{}` uses `{}`", pin.tool, pin.version, kit, id);
+                found.push((kit, id));
+            }
+            Err(e) => println!("skipped {}", e),
         }
     }
+    if found.is_empty() {
+        panic!("no pinned version matches a path in the config");
+    }
+
+    edit_config(false, exe, |doc| found.iter().try_for_each(|(kit, id)| edit::use_kit(doc, kit, id)));
 }
 
+/// Writes versions of kits in use to `.tool-versions` in cwd, keeping lines of other plugins.
+fn export_tool_versions() {
+    let config = cfgreader::get_config().unwrap_or_else(|e| panic!("{}", e));
+    let found = pins::tool_versions(&config);
+    if found.is_empty() {
+        panic!("no kit for any of {} is in use", pins::TOOL_VERSIONS_PLUGINS.join(", "));
+    }
 
-fn make_inline_tbls(paths_arr: &mut ArrayOfTables) {
-    for idx in 0..paths_arr.len() {
-        let sub = paths_arr.get_mut(idx).expect("expected array element");
-        let tgt_raw = sub.entry("target");
+    let mut versions = Vec::new();
+    for (plugin, version) in found {
+        match version {
+            Ok(version) => versions.push((plugin, version)),
+            Err(reason) => eprintln!("warning: skipping {}: {}", plugin, reason),
+        }
+    }
+    if versions.is_empty() {
+        panic!("none of the versions in use can be written to {}", pins::TOOL_VERSIONS);
+    }
 
-        tgt_raw.as_inline_table_mut().map(|e| e.fmt());
-        let tgt_table = tgt_raw.as_table().expect("expected table");
-        let mut tgt_inline = InlineTable::default();
-        tgt_table.iter().for_each(|i| {
-            let key = i.0;
-            let value = i.1.as_value().expect("expected toml value").to_owned();
-            tgt_inline.get_or_insert(key, value);
-        });
-        tgt_inline.fmt();
+    let cwd = env::current_dir().unwrap_or_else(|e| panic!("cannot determine current directory: {}", e));
+    let path = cwd.join(pins::TOOL_VERSIONS);
+    let existing = fs::read_to_string(&path).unwrap_or_default();
 
+    let content = pins::merge_tool_versions(&existing, &versions);
+    fs::write(&path, content).unwrap_or_else(|e| panic!("cannot write {}: {}", path.display(), e));
+    println!("updated {}", path.display());
+    versions.iter().for_each(|(plugin, version)| println!("  {} {}", plugin, version));
+}
 
-        let tgt_val = Value::InlineTable(tgt_inline);
-        let tgt_item = value(tgt_val);
-        *tgt_raw = tgt_item;
+fn format_config(path: Option<&str>) {
+    let path = config_to_edit(path);
+    let mut doc = edit::load(&path).unwrap_or_else(|e| panic!("{}", e));
+    edit::normalize(&mut doc);
+    edit::save(&path, &doc).unwrap_or_else(|e| panic!("{}", e));
+    println!("formatted {}", path.display());
+}
+
+fn migrate_config(path: Option<&str>) {
+    let path = config_to_edit(path);
+    let mut doc = edit::load(&path).unwrap_or_else(|e| panic!("{}", e));
+    let (from, notes) = edit::migrate(&mut doc).unwrap_or_else(|e| panic!("cannot migrate {}: {}", path.display(), e));
+
+    if from == CONFIG_VERSION {
+        println!("{} is already at version {}", path.display(), CONFIG_VERSION);
+        return;
     }
+
+    edit::save(&path, &doc).unwrap_or_else(|e| panic!("{}", e));
+    println!("migrated {} from version {} to {}", path.display(), from, CONFIG_VERSION);
+    notes.iter().for_each(|n| println!("  {}", n));
 }
@@ -0,0 +1,435 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cfg::{Config, KitConfig, LinkTarget, FileConfig, SdkTarget};
+use crate::detect::version_cmp;
+
+/// Version files of other managers, in the order they are read.
+pub const PIN_FILES: [&str; 6] = [".sdkmanrc", ".nvmrc", ".node-version", ".java-version", ".python-version", TOOL_VERSIONS];
+
+/// asdf and mise config, read like a project config even without `pin_files`.
+pub const TOOL_VERSIONS: &str = ".tool-versions";
+
+/// asdf plugins `binlink export --tool-versions` writes besides those in `tool_kits`.
+pub const TOOL_VERSIONS_PLUGINS: [&str; 5] = ["java", "nodejs", "python", "sbt", "scala"];
+
+/// A tool version declared for another manager, e.g. `java=17.0.5-tem` in `.sdkmanrc`.
+#[derive(Debug)]
+pub struct Pin {
+    pub tool: String,
+    pub version: String,
+    pub file: PathBuf,
+}
+
+/// Pins declared in one version file, empty when it can't be read.
+pub fn read(file: &Path) -> Vec<Pin> {
+    let content = match fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(_) => return Vec::new(),
+    };
+    let lines = content.lines()
+        .map(|l| l.split('#').next().unwrap_or_default().trim())
+        .filter(|l| !l.is_empty());
+    let pin = |tool: &str, version: &str| Pin { tool: String::from(tool), version: String::from(version), file: file.to_path_buf() };
+
+    match file.file_name().and_then(|n| n.to_str()) {
+        Some(".sdkmanrc") => lines
+            .filter_map(|l| l.split_once('='))
+            .map(|(tool, version)| pin(tool.trim(), version.trim()))
+            .collect(),
+        Some(".nvmrc") | Some(".node-version") => lines.take(1).map(|v| pin("node", v)).collect(),
+        Some(".java-version") => lines.take(1).map(|v| pin("java", v)).collect(),
+        // pyenv falls back to later lines, the first one is what `python` runs
+        Some(".python-version") => lines.take(1).map(|v| pin("python", v)).collect(),
+        // `python 3.11.1 3.10.4`, same fallback per plugin
+        Some(TOOL_VERSIONS) => lines
+            .filter_map(|l| {
+                let mut words = l.split_whitespace();
+                Some(pin(words.next()?, words.next()?))
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+pub fn always_read(file: &Path) -> bool {
+    file.file_name().map(|n| n == TOOL_VERSIONS).unwrap_or(false)
+}
+
+/// Pins from all version files in `dir`.
+pub fn read_dir(dir: &Path) -> Vec<Pin> {
+    PIN_FILES.iter().flat_map(|name| read(&dir.join(name))).collect()
+}
+
+impl Pin {
+    /// The kit providing this tool in `config` and the id of a path with the pinned version.
+    ///
+    /// Paths match when their id or target has the version as a component,
+    /// partial versions like `17` pick the highest matching one.
+    pub fn resolve(&self, config: &Config) -> Result<(String, String), String> {
+        if !self.version.contains(|c: char| c.is_ascii_digit()) {
+            return Err(format!("{} `{}` in {}: aliases are not supported", self.tool, self.version, self.file.display()));
+        }
+
+        let bins = tool_bins(&self.tool);
+        let kit = kit_for(&self.tool, config)
+            .ok_or_else(|| format!("{} in {}: `{}` is not mapped to a kit", self.tool, self.file.display(), bins[0]))?;
+
+        // names a path must mention, so `node-v17` never serves `java 17`
+        let mut names: Vec<&str> = bins.iter().map(String::as_str).collect();
+        names.push(&self.tool);
+        names.push(&kit);
+
+        // asdf names `temurin-17.0.5+8` what sdkman calls `17.0.5-tem`, retry with just the number
+        let version = self.version.trim_start_matches('v');
+        let number: String = version.chars()
+            .skip_while(|c| !c.is_ascii_digit())
+            .take_while(|c| c.is_ascii_digit() || *c == '.')
+            .collect();
+
+        [version, number.as_str()].iter()
+            .filter(|v| !v.is_empty())
+            .find_map(|v| best_path(config, &names, v))
+            .map(|id| (kit.clone(), id))
+            .ok_or_else(|| format!("{} {} in {}: no path in the config matches", self.tool, self.version, self.file.display()))
+    }
+}
+
+/// Kit of a tool: set in `tool_kits`, or the one its binary is mapped to.
+fn kit_for(tool: &str, config: &Config) -> Option<String> {
+    if let Some(kit) = config.layers.iter().rev().find_map(|l| l.config.tool_kits().get(tool)) {
+        return Some(kit.clone());
+    }
+
+    let sets = config.binsets();
+    tool_bins(tool).iter()
+        .find_map(|b| config.layers.iter().rev().find_map(|l| l.config.find_bin(b, &config.facts, &sets)))
+        .and_then(|b| match &b.target {
+            LinkTarget::Sdk { name } => Some(name.clone()),
+            LinkTarget::Default => None,
+        })
+}
+
+/// Id of the path whose id or target has `version` as a component, the highest one for partial versions.
+fn best_path(config: &Config, names: &[&str], version: &str) -> Option<String> {
+    let ids = config.layers.iter()
+        .flat_map(|l| l.config.paths().iter())
+        .filter(|p| p.when.as_ref().map(|w| w.matches(&config.facts)).unwrap_or(true))
+        .filter_map(|p| {
+            let texts: Vec<&str> = match &p.target {
+                SdkTarget::Dir { path } => vec![&p.id, path],
+                SdkTarget::HighestPrefix { base, prefix, .. } => vec![&p.id, base, prefix],
+                SdkTarget::Sdkman { candidate, version } => vec![&p.id, candidate, version],
+                SdkTarget::Nvm { version } => vec![&p.id, "node", version],
+                SdkTarget::Pyenv { version } => vec![&p.id, "python", version],
+                SdkTarget::Jabba { name } => vec![&p.id, name],
+                SdkTarget::Asdf { plugin, version } => vec![&p.id, plugin, version],
+            };
+            let relevant = texts.iter().any(|t| names.iter().any(|n| t.contains(n)));
+            let matched = texts.iter()
+                .flat_map(|t| t.split('/'))
+                .filter(|c| has_version(c, version))
+                .max_by(|a, b| version_cmp(a, b));
+            matched.filter(|_| relevant).map(|c| (c, &p.id))
+        });
+
+    // on ties an id naming the version beats a kit's default path
+    ids.max_by(|(a, x), (b, y)| version_cmp(a, b).then(x.contains(version).cmp(&y.contains(version))))
+        .map(|(_, id)| id.clone())
+}
+
+/// `.tool-versions` entries for kits of known plugins, versions taken from the directories they resolve to.
+///
+/// Plugins whose kit is in use but whose version asdf can't name come with the reason instead.
+pub fn tool_versions(config: &Config) -> Vec<(String, Result<String, String>)> {
+    let mut plugins: Vec<String> = TOOL_VERSIONS_PLUGINS.iter().map(|p| String::from(*p)).collect();
+    config.layers.iter()
+        .flat_map(|l| l.config.tool_kits().keys())
+        .for_each(|p| if !plugins.contains(p) { plugins.push(p.clone()) });
+
+    plugins.into_iter()
+        .filter_map(|plugin| {
+            let kit = kit_for(&plugin, config)?;
+            let version = match config.kit_dir(&kit).ok().and_then(|d| version_of(&d)) {
+                Some(dir) => plugin_version(&plugin, &dir)
+                    .ok_or_else(|| format!("`{}` of kit `{}` has no {} version asdf knows", dir, kit, plugin)),
+                None => Err(format!("no version in the path of kit `{}`", kit)),
+            };
+            Some((plugin, version))
+        })
+        .collect()
+}
+
+/// `existing` `.tool-versions` content with the lines of `versions` replaced or appended, other plugins kept.
+pub fn merge_tool_versions(existing: &str, versions: &[(String, String)]) -> String {
+    let mut pending = versions.to_vec();
+    let mut lines: Vec<String> = existing.lines()
+        .map(|line| {
+            let plugin = line.split_whitespace().next().unwrap_or_default();
+            match pending.iter().position(|(p, _)| p == plugin) {
+                Some(idx) => {
+                    let (plugin, version) = pending.remove(idx);
+                    format!("{} {}", plugin, version)
+                }
+                None => String::from(line),
+            }
+        })
+        .collect();
+    lines.extend(pending.into_iter().map(|(plugin, version)| format!("{} {}", plugin, version)));
+
+    lines.join("\n") + "\n"
+}
+
+/// Last of `dir` and its parent with a digit, e.g. `17.0.5-tem` for `~/.sdkman/candidates/java/current/bin`.
+///
+/// Components further up name where tools are installed, not which version.
+fn version_of(dir: &Path) -> Option<String> {
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    dir.components().rev().take(2)
+        .filter_map(|c| c.as_os_str().to_str())
+        .find(|c| c.contains(|ch: char| ch.is_ascii_digit()))
+        .map(String::from)
+}
+
+/// asdf-java distributions, their versions start with the name, e.g. `temurin-17.0.5+8`.
+const ASDF_JAVA_VENDORS: [&str; 14] = [
+    "adoptopenjdk", "corretto", "dragonwell", "graalvm", "kona", "liberica", "mandrel", "microsoft", "openjdk", "oracle",
+    "sapmachine", "semeru", "temurin", "zulu",
+];
+
+/// Install directory `name` as `plugin` names the version, `None` when that can't be told from the name.
+fn plugin_version(plugin: &str, name: &str) -> Option<String> {
+    let numeric = |v: &str| v.starts_with(|c: char| c.is_ascii_digit());
+    match plugin {
+        "java" => {
+            // installed by asdf itself
+            if let Some((vendor, version)) = name.split_once('-') {
+                if ASDF_JAVA_VENDORS.contains(&vendor) && numeric(version) {
+                    return Some(String::from(name));
+                }
+            }
+            // sdkman puts the vendor last; others number versions differently or need the build, `17.0.5-tem` is `temurin-17.0.5+8`
+            match name.rsplit_once('-')? {
+                (version, "open") if numeric(version) => Some(format!("openjdk-{}", version)),
+                (version, "amzn") if numeric(version) => Some(format!("corretto-{}", version)),
+                _ => None,
+            }
+        }
+        // pyenv and asdf-python both name versions after python-build
+        "python" => Some(String::from(name)),
+        // nvm's `v18.12.1` is `18.12.1` to asdf
+        "nodejs" | "sbt" | "scala" => Some(name.strip_prefix('v').unwrap_or(name)).filter(|v| numeric(v)).map(String::from),
+        _ => Some(String::from(name)),
+    }
+}
+
+/// A project layer pointing kits at pinned versions, for pins which resolve.
+pub fn layer_config(pins: &[Pin], config: &Config) -> FileConfig {
+    let kits = pins.iter()
+        .filter_map(|pin| pin.resolve(config).map_err(|e| log::warn!("ignoring pin: {}", e)).ok())
+        .map(|(name, id)| KitConfig { name, id, on_missing: None, when: None })
+        .collect();
+
+    FileConfig { kits, ..FileConfig::empty() }
+}
+
+/// Binaries a tool is known by, the first one is used in messages.
+fn tool_bins(tool: &str) -> Vec<String> {
+    match tool {
+        "maven" => vec![String::from("mvn")],
+        "nodejs" => vec![String::from("node")],
+        "python" => vec![String::from("python"), String::from("python3")],
+        other => vec![String::from(other)],
+    }
+}
+
+/// `17` is in `17.0.5-tem`, `java-17-openjdk` and `v17.1`, but not in `171` or `1.17`.
+fn has_version(component: &str, version: &str) -> bool {
+    component.match_indices(version).any(|(idx, _)| {
+        let before = component[..idx].chars().last();
+        let after = component[idx + version.len()..].chars().next();
+        let starts = match before {
+            None => true,
+            Some('v') => !component[..idx - 1].ends_with(char::is_alphanumeric),
+            Some(c) => !c.is_alphanumeric() && c != '.',
+        };
+        starts && !after.map(|c| c.is_ascii_digit()).unwrap_or(false)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::{Layer, LayerKind};
+    use crate::cond::Facts;
+    use crate::testutil::temp_dir;
+
+    const CONFIG: &str = r#"
+version = 2
+bins = [ { name = "java", kit = "jdk" }, { name = "node", kit = "node" }, { name = "python", kit = "python" } ]
+kits = [ { name = "jdk", id = "jdk-default" }, { name = "node", id = "node-v18.9.0" }, { name = "python", id = "python-17" } ]
+paths = [
+  { id = "java-11.0.2-open", target = { type = "Dir", path = "~/.sdkman/candidates/java/11.0.2-open/bin" } },
+  { id = "java-17.0.5-tem", target = { type = "Dir", path = "~/.sdkman/candidates/java/17.0.5-tem/bin" } },
+  { id = "java-17.0.12-tem", target = { type = "Dir", path = "~/.sdkman/candidates/java/17.0.12-tem/bin" } },
+  { id = "jdk-default", target = { type = "Dir", path = "~/.sdkman/candidates/java/17.0.12-tem/bin" } },
+  { id = "node-v18.9.0", target = { type = "Dir", path = "~/.nvm/versions/node/v18.9.0/bin" } },
+  { id = "node-v18.12.1", target = { type = "Dir", path = "~/.nvm/versions/node/v18.12.1/bin" } },
+  { id = "node-v17.1.0", target = { type = "Dir", path = "~/.nvm/versions/node/v17.1.0/bin" } },
+  { id = "node-v171.0.0", target = { type = "Dir", path = "~/.nvm/versions/node/v171.0.0/bin" } },
+  { id = "python-17", target = { type = "Dir", path = "/opt/python-17/bin" } },
+]
+"#;
+
+    fn config() -> Config {
+        config_of(CONFIG)
+    }
+
+    fn config_of(content: &str) -> Config {
+        let layer = Layer {
+            kind: LayerKind::User,
+            path: PathBuf::from("/home/me/.config/binlink/binlink.toml"),
+            includes: Vec::new(),
+            watched: Vec::new(),
+            config: Box::new(toml::from_str::<crate::cfg::FileConfig>(content).expect("valid config")),
+        };
+        Config { layers: vec![layer], facts: Facts::detect() }
+    }
+
+    fn resolve(tool: &str, version: &str) -> Result<(String, String), String> {
+        let pin = Pin { tool: String::from(tool), version: String::from(version), file: PathBuf::from(".pins") };
+        pin.resolve(&config())
+    }
+
+    fn ok(kit: &str, id: &str) -> Result<(String, String), String> {
+        Ok((String::from(kit), String::from(id)))
+    }
+
+    #[test]
+    fn finds_versions_as_components() {
+        assert!(has_version("17.0.5-tem", "17"));
+        assert!(has_version("java-17-openjdk", "17"));
+        assert!(has_version("v17.1", "17"));
+        assert!(has_version("node-v18.12.1", "18.12.1"));
+        assert!(!has_version("171", "17"));
+        assert!(!has_version("1.17", "17"));
+        assert!(!has_version("jdk17", "17"));
+        assert!(!has_version("dev17", "17"));
+        assert!(!has_version("17.0.5-tem", "17.0.50"));
+    }
+
+    #[test]
+    fn reads_version_files() {
+        let dir = temp_dir("pins-read");
+        fs::write(dir.join(".sdkmanrc"), "# sdk env\njava=17.0.5-tem\nsbt = 1.9.0  # build\n\n").unwrap();
+        fs::write(dir.join(".nvmrc"), "v18\n").unwrap();
+        fs::write(dir.join(".tool-versions"), "python 3.11.1 3.10.4\njava temurin-17.0.5+8\n").unwrap();
+
+        let pins: Vec<(String, String)> = read_dir(&dir).into_iter().map(|p| (p.tool, p.version)).collect();
+        let expected: Vec<(String, String)> = [
+            ("java", "17.0.5-tem"), ("sbt", "1.9.0"), ("node", "v18"), ("python", "3.11.1"), ("java", "temurin-17.0.5+8"),
+        ].iter().map(|(t, v)| (String::from(*t), String::from(*v))).collect();
+        assert_eq!(pins, expected);
+    }
+
+    #[test]
+    fn resolves_sdkmanrc_versions() {
+        assert_eq!(resolve("java", "17.0.5-tem"), ok("jdk", "java-17.0.5-tem"));
+        assert_eq!(resolve("java", "11.0.2-open"), ok("jdk", "java-11.0.2-open"));
+        // highest 17, on the tie with the kit's default path the id naming the version wins
+        assert_eq!(resolve("java", "17"), ok("jdk", "java-17.0.12-tem"));
+        assert!(resolve("java", "21.0.1-tem").is_err());
+    }
+
+    #[test]
+    fn resolves_nvmrc_versions() {
+        assert_eq!(resolve("node", "v18"), ok("node", "node-v18.12.1"));
+        assert_eq!(resolve("node", "18"), ok("node", "node-v18.12.1"));
+        assert_eq!(resolve("node", "18.9"), ok("node", "node-v18.9.0"));
+        // `171` is not a 17, `python-17` doesn't serve node
+        assert_eq!(resolve("node", "17"), ok("node", "node-v17.1.0"));
+        assert!(resolve("node", "lts/hydrogen").unwrap_err().contains("aliases are not supported"));
+    }
+
+    #[test]
+    fn resolves_asdf_versions_by_their_number() {
+        assert_eq!(resolve("java", "temurin-17.0.5+8"), ok("jdk", "java-17.0.5-tem"));
+        assert_eq!(resolve("nodejs", "18.12.1"), ok("node", "node-v18.12.1"));
+        assert!(resolve("ruby", "3.2.0").unwrap_err().contains("not mapped to a kit"));
+    }
+
+    #[test]
+    fn requires_paths_mentioning_the_tool() {
+        // only `python-17` has a 17 component besides java and node paths
+        assert_eq!(resolve("python", "17"), ok("python", "python-17"));
+        assert!(resolve("python", "11.0.2").is_err());
+    }
+
+    #[test]
+    fn names_versions_like_asdf_plugins() {
+        let version = |plugin: &str, name: &str| plugin_version(plugin, name);
+        assert_eq!(version("java", "temurin-17.0.5+8"), Some(String::from("temurin-17.0.5+8")));
+        assert_eq!(version("java", "17.0.2-open"), Some(String::from("openjdk-17.0.2")));
+        assert_eq!(version("java", "17.0.5.8.1-amzn"), Some(String::from("corretto-17.0.5.8.1")));
+        // asdf-java wants the build number, distro directories name no distribution it knows
+        assert_eq!(version("java", "17.0.5-tem"), None);
+        assert_eq!(version("java", "java-17-openjdk-amd64"), None);
+        assert_eq!(version("java", "jdk-17"), None);
+
+        assert_eq!(version("nodejs", "v18.12.1"), Some(String::from("18.12.1")));
+        assert_eq!(version("nodejs", "node-v18"), None);
+        assert_eq!(version("sbt", "1.9.0"), Some(String::from("1.9.0")));
+        assert_eq!(version("python", "pypy3.9-7.3.9"), Some(String::from("pypy3.9-7.3.9")));
+        assert_eq!(version("protoc", "21.12"), Some(String::from("21.12")));
+    }
+
+    #[test]
+    fn takes_versions_from_resolved_directories() {
+        let dir = temp_dir("pins-version-of");
+        fs::create_dir_all(dir.join("candidates/java/17.0.2-open/bin")).unwrap();
+        std::os::unix::fs::symlink("17.0.2-open", dir.join("candidates/java/current")).unwrap();
+
+        assert_eq!(version_of(&dir.join("candidates/java/current/bin")), Some(String::from("17.0.2-open")));
+        assert_eq!(version_of(&dir.join("node/v18.12.1/bin")), Some(String::from("v18.12.1")));
+        assert_eq!(version_of(Path::new("/usr/bin")), None);
+    }
+
+    #[test]
+    fn exports_versions_of_kits_in_use() {
+        let dir = temp_dir("pins-tool-versions");
+        crate::testutil::mkdirs(&dir, &["17.0.5-tem/bin", "v18.12.1/bin", "protoc-21.12/bin", "python/bin"]);
+        let config = config_of(&format!(r#"
+version = 2
+bins = [ {{ name = "java", kit = "jdk" }}, {{ name = "node", kit = "node" }}, {{ name = "python", kit = "python" }} ]
+kits = [
+  {{ name = "jdk", id = "jdk" }}, {{ name = "node", id = "node" }},
+  {{ name = "python", id = "python" }}, {{ name = "protoc", id = "protoc" }},
+]
+paths = [
+  {{ id = "jdk", target = {{ type = "Dir", path = "{dir}/17.0.5-tem/bin" }} }},
+  {{ id = "node", target = {{ type = "Dir", path = "{dir}/v18.12.1/bin" }} }},
+  {{ id = "python", target = {{ type = "Dir", path = "{dir}/python/bin" }} }},
+  {{ id = "protoc", target = {{ type = "Dir", path = "{dir}/protoc-21.12/bin" }} }},
+]
+tool_kits = {{ protoc = "protoc" }}
+"#, dir = dir.display()));
+
+        let versions = tool_versions(&config);
+        let plugins: Vec<&str> = versions.iter().map(|(p, _)| p.as_str()).collect();
+        assert_eq!(plugins, ["java", "nodejs", "python", "protoc"]);
+        assert_eq!(versions[0].1, Err(String::from("`17.0.5-tem` of kit `jdk` has no java version asdf knows")));
+        assert_eq!(versions[1].1, Ok(String::from("18.12.1")));
+        assert_eq!(versions[2].1, Err(String::from("no version in the path of kit `python`")));
+        assert_eq!(versions[3].1, Ok(String::from("protoc-21.12")));
+    }
+
+    #[test]
+    fn merges_tool_versions_lines() {
+        let versions = [(String::from("java"), String::from("openjdk-17.0.2")), (String::from("nodejs"), String::from("18.12.1"))];
+        assert_eq!(merge_tool_versions("", &versions), "java openjdk-17.0.2\nnodejs 18.12.1\n");
+        assert_eq!(
+            merge_tool_versions("# pinned\nruby 3.2.0\njava temurin-11.0.2+9 # old\n", &versions),
+            "# pinned\nruby 3.2.0\njava openjdk-17.0.2\nnodejs 18.12.1\n",
+        );
+    }
+}
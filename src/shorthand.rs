@@ -0,0 +1,379 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::marker::PhantomData;
+
+use schemars::JsonSchema;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{Schema, SchemaObject, SubschemaValidation};
+use serde::{Deserialize, Deserializer};
+use serde::de::{Error as _, MapAccess, SeqAccess, Visitor};
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+
+use crate::cfg::{BinSelector, CONFIG_VERSION, FileConfig, KitConfig, KitPath, LinkTarget, LinkedBinary, OnMissing, SdkTarget, Argv0};
+use crate::cond::Condition;
+
+/// Config file as written: every section optional, `bins` and `kits` as arrays or as tables keyed by name,
+/// e.g. `[bins] java = "jdk"` and `[kits.jdk] path = "/usr/lib/jvm/java-17-openjdk-amd64/bin"`.
+///
+/// Entries are checked while they are read, so errors point at the offending line.
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RawConfig {
+    /// format version the file was written for, 1 when missing
+    #[serde(default, deserialize_with = "version")]
+    version: Option<i64>,
+    /// fail hard instead of falling back to `$PATH` when resolution breaks
+    #[serde(default)]
+    strict: Option<bool>,
+    /// honour `.sdkmanrc`, `.nvmrc` and similar version files as project configs
+    #[serde(default)]
+    pin_files: Option<bool>,
+    /// kits for `.tool-versions` plugins, e.g. `nodejs = "node"`
+    #[serde(default)]
+    tool_kits: BTreeMap<String, String>,
+    /// named binary lists, e.g. `jdk = ["java", "javac"]`, replacing built-in sets of the same name
+    #[serde(default)]
+    binsets: BTreeMap<String, Vec<String>>,
+    /// files merged underneath this one, relative to it, globs allowed
+    #[serde(default)]
+    include: Vec<String>,
+    /// what to do when a kit doesn't provide a binary mapped to it
+    #[serde(default)]
+    on_missing: Option<OnMissing>,
+    #[serde(default)]
+    bins: Entries<Checked<BinTable, false>, OrTable<Checked<BinTable, true>>>,
+    #[serde(default)]
+    kits: Entries<KitConfig, OrTable<Checked<KitTable, true>>>,
+    #[serde(default)]
+    paths: Vec<KitPath>,
+}
+
+/// `[bins] node = { kit = "graal", exec = "graalnode" }`, `target` instead of `kit` for anything but a kit.
+/// With `set` the entry maps a whole bin set and its key is only a label.
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct BinTable {
+    /// array form only, keyed tables are named by their key
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    set: Option<String>,
+    #[serde(default)]
+    kit: Option<String>,
+    #[serde(default)]
+    target: Option<LinkTarget>,
+    #[serde(default)]
+    exec: Option<String>,
+    #[serde(default)]
+    argv0: Option<Argv0>,
+    #[serde(default)]
+    when: Option<Condition>,
+}
+
+/// `[kits.jdk]` defining the kit and its path at once, `id` defaults to the kit name.
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct KitTable {
+    #[serde(default)]
+    id: Option<String>,
+    /// shorthand for a `Dir` target
+    #[serde(default)]
+    path: Option<String>,
+    #[serde(default)]
+    target: Option<SdkTarget>,
+    #[serde(default)]
+    on_missing: Option<OnMissing>,
+    #[serde(default)]
+    when: Option<Condition>,
+}
+
+fn version<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+    match i64::deserialize(deserializer)? {
+        v if v > CONFIG_VERSION => Err(D::Error::custom(format!("config version {} is newer than {} supported by this binlink", v, CONFIG_VERSION))),
+        v if v < 1 => Err(D::Error::custom(format!("invalid config version {}", v))),
+        v => Ok(Some(v)),
+    }
+}
+
+/// Rules serde can't express for one table.
+trait Check {
+    /// `keyed` for tables of the map form, which are named by their key
+    fn check(&self, keyed: bool) -> Result<(), String>;
+}
+
+impl Check for BinTable {
+    fn check(&self, keyed: bool) -> Result<(), String> {
+        match (&self.kit, &self.target) {
+            (Some(_), Some(_)) => return Err(String::from("`kit` and `target` are mutually exclusive")),
+            (None, None) => return Err(String::from("either `kit` or `target` is required")),
+            _ => (),
+        }
+
+        match (&self.name, &self.set) {
+            (Some(_), _) if keyed => Err(String::from("`name` is the key of a keyed table")),
+            (Some(_), Some(set)) => Err(format!("`name` and `set` = `{}` are mutually exclusive", set)),
+            (None, None) if !keyed => Err(String::from("either `name` or `set` is required")),
+            (_, Some(set)) if self.exec.is_some() => Err(format!("set `{}` can't share one `exec`", set)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Check for KitTable {
+    fn check(&self, _keyed: bool) -> Result<(), String> {
+        match (&self.path, &self.target) {
+            (Some(_), Some(_)) => Err(String::from("`path` and `target` are mutually exclusive")),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A table which passed `Check` right after it was read, so errors keep its position in the file.
+struct Checked<T, const KEYED: bool>(T);
+
+impl<'de, T: Deserialize<'de> + Check, const KEYED: bool> Deserialize<'de> for Checked<T, KEYED> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let table = T::deserialize(deserializer)?;
+        table.check(KEYED).map_err(D::Error::custom)?;
+        Ok(Checked(table))
+    }
+}
+
+impl<T: JsonSchema, const KEYED: bool> JsonSchema for Checked<T, KEYED> {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        T::schema_name()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        gen.subschema_for::<T>()
+    }
+}
+
+/// `key` is the name in the keyed form, the array form names entries with `name`.
+fn bin_entry(key: Option<String>, table: BinTable) -> LinkedBinary {
+    // the key of a set entry only labels it
+    let selector = match table.set {
+        Some(set) => BinSelector::Set { set },
+        None => BinSelector::Name { name: key.or(table.name).expect("checked while parsing") },
+    };
+    let target = match (table.kit, table.target) {
+        (Some(kit), _) => LinkTarget::Sdk { name: kit },
+        (None, target) => target.expect("checked while parsing"),
+    };
+
+    LinkedBinary { selector, target, exec: table.exec, argv0: table.argv0, when: table.when, origin: None }
+}
+
+fn kit_entry(name: String, spec: OrTable<Checked<KitTable, true>>) -> (KitConfig, Option<KitPath>) {
+    let table = match spec {
+        OrTable::Str(path) => KitTable { id: None, path: Some(path), target: None, on_missing: None, when: None },
+        OrTable::Table(Checked(table)) => table,
+    };
+
+    let target = match (table.path, table.target) {
+        (Some(path), _) => Some(SdkTarget::Dir { path }),
+        (None, target) => target,
+    };
+
+    let id = table.id.unwrap_or_else(|| name.clone());
+    let when = table.when;
+    let path = target.map(|target| KitPath { id: id.clone(), target, when: when.clone(), origin: None, literal: false });
+    let kit = KitConfig { name, id, on_missing: table.on_missing, when };
+
+    (kit, path)
+}
+
+impl From<RawConfig> for FileConfig {
+    fn from(raw: RawConfig) -> Self {
+        let bins = match raw.bins {
+            Entries::List(bins) => bins.into_iter().map(|Checked(table)| bin_entry(None, table)).collect(),
+            Entries::Map(map) => map.into_iter()
+                .map(|(name, spec)| match spec {
+                    OrTable::Str(kit) => {
+                        let table = BinTable { name: None, set: None, kit: Some(kit), target: None, exec: None, argv0: None, when: None };
+                        bin_entry(Some(name), table)
+                    }
+                    OrTable::Table(Checked(table)) => bin_entry(Some(name), table),
+                })
+                .collect(),
+        };
+
+        let mut paths = raw.paths;
+        let kits = match raw.kits {
+            Entries::List(kits) => kits,
+            Entries::Map(map) => map.into_iter()
+                .map(|(name, spec)| {
+                    let (kit, path) = kit_entry(name, spec);
+                    paths.extend(path);
+                    kit
+                })
+                .collect(),
+        };
+
+        FileConfig {
+            version: raw.version,
+            strict: raw.strict,
+            pin_files: raw.pin_files,
+            tool_kits: raw.tool_kits,
+            binsets: raw.binsets,
+            include: raw.include,
+            on_missing: raw.on_missing,
+            bins,
+            kits,
+            paths,
+        }
+    }
+}
+
+/// Array of full entries, or a table keyed by name.
+enum Entries<T, S> {
+    List(Vec<T>),
+    Map(BTreeMap<String, S>),
+}
+
+impl<T, S> Default for Entries<T, S> {
+    fn default() -> Self {
+        Entries::List(Vec::new())
+    }
+}
+
+impl<'de, T: Deserialize<'de>, S: Deserialize<'de>> Deserialize<'de> for Entries<T, S> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EntriesVisitor<T, S>(PhantomData<(T, S)>);
+
+        impl<'de, T: Deserialize<'de>, S: Deserialize<'de>> Visitor<'de> for EntriesVisitor<T, S> {
+            type Value = Entries<T, S>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an array of tables or a table keyed by name")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+                Vec::deserialize(SeqAccessDeserializer::new(seq)).map(Entries::List)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                BTreeMap::deserialize(MapAccessDeserializer::new(map)).map(Entries::Map)
+            }
+        }
+
+        deserializer.deserialize_any(EntriesVisitor(PhantomData))
+    }
+}
+
+impl<T: JsonSchema, S: JsonSchema> JsonSchema for Entries<T, S> {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        format!("Entries_{}_{}", T::schema_name(), S::schema_name())
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        any_of(vec![gen.subschema_for::<Vec<T>>(), gen.subschema_for::<BTreeMap<String, S>>()])
+    }
+}
+
+/// A plain string for the common case, or the full table.
+enum OrTable<T> {
+    Str(String),
+    Table(T),
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for OrTable<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct OrTableVisitor<T>(PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>> Visitor<'de> for OrTableVisitor<T> {
+            type Value = OrTable<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a string or a table")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(OrTable::Str(String::from(v)))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                T::deserialize(MapAccessDeserializer::new(map)).map(OrTable::Table)
+            }
+        }
+
+        deserializer.deserialize_any(OrTableVisitor(PhantomData))
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for OrTable<T> {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        format!("OrTable_{}", T::schema_name())
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        any_of(vec![gen.subschema_for::<String>(), gen.subschema_for::<T>()])
+    }
+}
+
+fn any_of(schemas: Vec<Schema>) -> Schema {
+    SchemaObject {
+        subschemas: Some(Box::new(SubschemaValidation { any_of: Some(schemas), ..Default::default() })),
+        ..Default::default()
+    }.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cfg::FileConfig;
+
+    /// Message and 1-based line of a TOML parse error.
+    fn error(content: &str) -> (String, usize) {
+        let e = toml::from_str::<FileConfig>(content).map(|_| ()).expect_err("config must be rejected");
+        let line = e.line_col().map(|(l, _)| l + 1).expect("error must have a position");
+        (e.to_string(), line)
+    }
+
+    #[test]
+    fn keyed_bin_errors_point_at_the_entry() {
+        let (message, line) = error("version = 2\n[bins]\njava = \"jdk\"\njavac = { name = \"javac\", kit = \"jdk\" }\n");
+        assert!(message.contains("`name` is the key of a keyed table"), "{}", message);
+        assert_eq!(line, 4);
+
+        let (message, line) = error("[bins]\njava = { kit = \"jdk\", target = { type = \"Default\" } }\n");
+        assert!(message.contains("`kit` and `target` are mutually exclusive"), "{}", message);
+        assert_eq!(line, 2);
+
+        let (message, line) = error("[bins]\njava = \"jdk\"\n\n[bins.tools]\nset = \"jdk\"\nkit = \"jdk\"\nexec = \"java\"\n");
+        assert!(message.contains("set `jdk` can't share one `exec`"), "{}", message);
+        assert_eq!(line, 4);
+    }
+
+    #[test]
+    fn array_bin_errors_point_at_the_entry() {
+        let (message, line) = error("[[bins]]\nname = \"java\"\nkit = \"jdk\"\n\n[[bins]]\nkit = \"jdk\"\n");
+        assert!(message.contains("either `name` or `set` is required"), "{}", message);
+        assert_eq!(line, 5);
+
+        let (message, _) = error("[[bins]]\nname = \"java\"\nset = \"jdk\"\nkit = \"jdk\"\n");
+        assert!(message.contains("`name` and `set` = `jdk` are mutually exclusive"), "{}", message);
+    }
+
+    #[test]
+    fn kit_and_version_errors_have_positions() {
+        let (message, line) = error("[kits.a]\npath = \"/a\"\n\n[kits.jdk]\npath = \"/a\"\ntarget = { type = \"Dir\", path = \"/b\" }\n");
+        assert!(message.contains("`path` and `target` are mutually exclusive"), "{}", message);
+        assert_eq!(line, 4);
+
+        // top-level keys only get the position of the root table
+        let (message, _) = error("version = 9\n");
+        assert!(message.contains("config version 9 is newer"), "{}", message);
+    }
+}
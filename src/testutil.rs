@@ -0,0 +1,30 @@
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Fresh empty directory under the system temp dir, unique per test and process.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("binlink-test-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap_or_else(|e| panic!("cannot create {}: {}", dir.display(), e));
+    dir
+}
+
+/// Creates `names` as empty directories in `dir`.
+pub fn mkdirs(dir: &Path, names: &[&str]) {
+    for name in names {
+        fs::create_dir_all(dir.join(name)).unwrap_or_else(|e| panic!("cannot create {}: {}", name, e));
+    }
+}
+
+/// Creates `names`, relative paths within `dir`, as empty executable files.
+pub fn executables(dir: &Path, names: &[&str]) {
+    for name in names {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().expect("file in a directory")).unwrap();
+        fs::write(&path, "").unwrap_or_else(|e| panic!("cannot create {}: {}", path.display(), e));
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+}